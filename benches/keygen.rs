@@ -0,0 +1,60 @@
+// Benchmarks for generating primes and RSA keys, and for what rejecting a candidate costs.
+// Run with: cargo bench --bench keygen
+// For the mean and tail over a batch of keys, with counts of where the prime search's candidates
+// went, use the keygen bench command instead. Modexp on its own is the bigmod group in the
+// multiply benchmarks. The biggest size is 4080 bits, since a Key can't hold a 4096-bit modulus
+
+use criterion::{ BenchmarkId, Criterion, criterion_group, criterion_main };
+use std::hint::black_box;
+
+use custom_user_network_transport::{ keygen, primes };
+use custom_user_network_transport::keygen::{ Key, NumberHandler };
+use custom_user_network_transport::profile::DEFAULT_MODULUS_BITS;
+
+// One prime for a modulus of each size
+fn bench_prime(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prime");
+    group.sample_size(10);
+    for bits in DEFAULT_MODULUS_BITS {
+        let mut handler = NumberHandler::new((bits >> 4) as usize).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(bits), &bits, |bench, &bits| {
+            bench.iter(|| handler.get_random_n_bit_prime(bits / 2).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// A whole key pair: two primes, the private exponent and validation
+fn bench_rsa_keys(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rsa_keys");
+    group.sample_size(10);
+    for bits in DEFAULT_MODULUS_BITS {
+        let mut handler = NumberHandler::new((bits >> 4) as usize).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(bits), &bits, |bench, _| {
+            bench.iter(|| handler.get_rsa_keys().unwrap())
+        });
+    }
+    group.finish();
+}
+
+// Throwing out a candidate with trial division, which is one pass over its limbs for all the
+// small prime products, against the Baillie-PSW test a candidate has to fail otherwise
+fn bench_rejection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rejection");
+    for bits in DEFAULT_MODULUS_BITS {
+        let mut handler = NumberHandler::new((bits >> 4) as usize).unwrap();
+        // Odd, and almost certainly composite
+        let candidate: Key = handler.get_random_n_bit_key(bits / 2).unwrap() | Key::ONE;
+        let mut remainders: [u64; primes::PRIME_PRODUCT_COUNT] = [0; primes::PRIME_PRODUCT_COUNT];
+        group.bench_with_input(BenchmarkId::new("trial_division", bits), &bits, |bench, _| {
+            bench.iter(|| keygen::rem_u64_batch(black_box(candidate), &primes::PRIME_PRODUCTS, &mut remainders))
+        });
+        group.bench_with_input(BenchmarkId::new("baillie_psw", bits), &bits, |bench, _| {
+            bench.iter(|| keygen::passes_baillie_psw(black_box(candidate)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_prime, bench_rsa_keys, bench_rejection);
+criterion_main!(benches);
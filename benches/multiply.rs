@@ -0,0 +1,103 @@
+// Benchmarks for the limb multiplication in src/multiply.rs and the bigmod built on it.
+// Run with: cargo bench --bench multiply
+// KARATSUBA_THRESHOLD and KARATSUBA_SQUARE_THRESHOLD come from comparing the schoolbook
+// and karatsuba results here, and MONTGOMERY_MIN_OPERATIONS from the reduce group.
+// bigmod stops at 4094 bits, the biggest modulus a Key allows
+
+use criterion::{ BenchmarkId, Criterion, criterion_group, criterion_main };
+use rand::prelude::*;
+use std::hint::black_box;
+
+use custom_user_network_transport::{ keygen, multiply };
+use custom_user_network_transport::keygen::Key;
+use custom_user_network_transport::modular::{ BarrettContext, MontgomeryContext };
+
+const SIZES: [usize; 4] = [1024, 2048, 4096, 8192];
+
+fn random_limbs(rng: &mut ThreadRng, bits: usize) -> Vec<u64> {
+    (0..(bits / 64)).map(|_| rng.random()).collect()
+}
+// A random number of exactly this many bits, odd so it works as a modulus
+fn random_key(rng: &mut ThreadRng, bits: u32) -> Key {
+    let mut bytes: [u8; Key::BYTES as usize] = [0; Key::BYTES as usize];
+    rng.fill(&mut bytes[..(bits.div_ceil(8) as usize)]);
+    let key: Key = Key::from_le_slice(&bytes).unwrap() & ((Key::ONE << bits) - Key::ONE);
+    key | (Key::ONE << (bits - 1)) | Key::ONE
+}
+
+fn bench_mul(c: &mut Criterion) {
+    let mut rng = rand::rng();
+    let mut group = c.benchmark_group("mul");
+    for bits in SIZES {
+        let (a, b) = (random_limbs(&mut rng, bits), random_limbs(&mut rng, bits));
+        let mut out: Vec<u64> = vec![0; a.len() + b.len()];
+        group.bench_with_input(BenchmarkId::new("schoolbook", bits), &bits, |bench, _| {
+            bench.iter(|| multiply::mul_schoolbook(black_box(&a), black_box(&b), &mut out))
+        });
+        group.bench_with_input(BenchmarkId::new("karatsuba", bits), &bits, |bench, _| {
+            bench.iter(|| multiply::mul(black_box(&a), black_box(&b), &mut out))
+        });
+    }
+    group.finish();
+}
+
+fn bench_square(c: &mut Criterion) {
+    let mut rng = rand::rng();
+    let mut group = c.benchmark_group("square");
+    for bits in SIZES {
+        let a: Vec<u64> = random_limbs(&mut rng, bits);
+        let mut out: Vec<u64> = vec![0; 2 * a.len()];
+        group.bench_with_input(BenchmarkId::new("schoolbook", bits), &bits, |bench, _| {
+            bench.iter(|| multiply::square_schoolbook(black_box(&a), &mut out))
+        });
+        group.bench_with_input(BenchmarkId::new("karatsuba", bits), &bits, |bench, _| {
+            bench.iter(|| multiply::square(black_box(&a), &mut out))
+        });
+    }
+    group.finish();
+}
+
+fn bench_bigmod(c: &mut Criterion) {
+    let mut rng = rand::rng();
+    let mut group = c.benchmark_group("bigmod");
+    group.sample_size(10);
+    for bits in [1024, 2048, keygen::MAX_MODULUS_BITS] {
+        let modulus: Key = random_key(&mut rng, bits);
+        let base: Key = random_key(&mut rng, bits - 1);
+        let exponent: Key = random_key(&mut rng, bits);
+        group.bench_with_input(BenchmarkId::from_parameter(bits), &bits, |bench, _| {
+            bench.iter(|| keygen::bigmod(black_box(base), black_box(exponent), modulus).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// A run of products by one modulus, counting the setup each context needs, against dividing every time
+fn bench_reduce(c: &mut Criterion) {
+    let mut rng = rand::rng();
+    let mut group = c.benchmark_group("reduce");
+    let modulus: Key = random_key(&mut rng, 2048);
+    let factor: Key = random_key(&mut rng, 2047);
+    for operations in [1, 4, 8, 16, 64] {
+        group.bench_with_input(BenchmarkId::new("division", operations), &operations, |bench, &operations| {
+            bench.iter(|| (0..operations).fold(factor, |x, _| keygen::mul_mod(x, factor, black_box(modulus))))
+        });
+        group.bench_with_input(BenchmarkId::new("barrett", operations), &operations, |bench, &operations| {
+            bench.iter(|| {
+                let context: BarrettContext = BarrettContext::new(black_box(modulus)).unwrap();
+                (0..operations).fold(factor, |x, _| context.mul_mod(x, factor))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("montgomery", operations), &operations, |bench, &operations| {
+            bench.iter(|| {
+                let context: MontgomeryContext = MontgomeryContext::new(black_box(modulus)).unwrap();
+                let factor: Key = context.to_montgomery(factor);
+                context.from_montgomery((0..operations).fold(factor, |x, _| context.mul(x, factor)))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_mul, bench_square, bench_bigmod, bench_reduce);
+criterion_main!(benches);
@@ -0,0 +1,262 @@
+/* ChaCha20-Poly1305 authenticated encryption, as specified in RFC 8439.
+    Used to encrypt private keys at rest. Quoted comments come from the RFC. */
+
+/// ChaCha20 keys are 256 bits
+pub const KEY_BYTES: usize = 32;
+/// The RFC 8439 nonce is 96 bits
+pub const NONCE_BYTES: usize = 12;
+/// The Poly1305 tag that follows the ciphertext
+pub const TAG_BYTES: usize = 16;
+
+/// The ciphertext or the associated data were changed, or the key is wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticationFailed;
+
+// "The ChaCha20 state is initialized as follows: The first four words (0-3) are constants"
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+#[inline]
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]); state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]); state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]); state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]); state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+// One 64 byte block of keystream
+fn chacha20_block(key: &[u8; KEY_BYTES], counter: u32, nonce: &[u8; NONCE_BYTES]) -> [u8; 64] {
+    let mut initial: [u32; 16] = [0; 16];
+    initial[..4].copy_from_slice(&CONSTANTS);
+    for ind in 0..8 {
+        initial[4 + ind] = u32::from_le_bytes([key[ind * 4], key[ind * 4 + 1], key[ind * 4 + 2], key[ind * 4 + 3]]);
+    }
+    initial[12] = counter;
+    for ind in 0..3 {
+        initial[13 + ind] = u32::from_le_bytes([nonce[ind * 4], nonce[ind * 4 + 1], nonce[ind * 4 + 2], nonce[ind * 4 + 3]]);
+    }
+
+    // "20 rounds, alternating between column rounds and diagonal rounds"
+    let mut state: [u32; 16] = initial;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut output: [u8; 64] = [0; 64];
+    for ind in 0..16 {
+        output[(ind * 4)..(ind * 4 + 4)].copy_from_slice(&state[ind].wrapping_add(initial[ind]).to_le_bytes());
+    }
+    output
+}
+// XOR the data with the keystream, starting at the given block counter
+fn chacha20_xor(key: &[u8; KEY_BYTES], counter: u32, nonce: &[u8; NONCE_BYTES], data: &mut [u8]) {
+    for (block_index, chunk) in data.chunks_mut(64).enumerate() {
+        let keystream: [u8; 64] = chacha20_block(key, counter + block_index as u32, nonce);
+        for (byte, key_byte) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= *key_byte;
+        }
+    }
+}
+
+// Poly1305 with the 130-bit accumulator split into five 26-bit limbs, so every product fits in a u64
+struct Poly1305 {
+    r: [u32; 5],
+    s: [u32; 4],
+    accumulator: [u32; 5]
+}
+impl Poly1305 {
+    fn new(key: &[u8; 32]) -> Self {
+        let word = |ind: usize| u32::from_le_bytes([key[ind], key[ind + 1], key[ind + 2], key[ind + 3]]);
+        // "r[3], r[7], r[11], and r[15] are required to have their top four bits clear...
+        // r[4], r[8], and r[12] are required to have their bottom two bits clear"
+        let r: [u32; 5] = [
+            word(0) & 0x3ffffff,
+            (word(3) >> 2) & 0x3ffff03,
+            (word(6) >> 4) & 0x3ffc0ff,
+            (word(9) >> 6) & 0x3f03fff,
+            (word(12) >> 8) & 0x00fffff
+        ];
+        Self { r, s: [word(16), word(20), word(24), word(28)], accumulator: [0; 5] }
+    }
+
+    // Add one 16 byte block, plus the 2^128 bit that follows every full block, then multiply by r
+    fn block(&mut self, block: &[u8; 16]) {
+        let word = |ind: usize| u32::from_le_bytes([block[ind], block[ind + 1], block[ind + 2], block[ind + 3]]);
+
+        let h: &mut [u32; 5] = &mut self.accumulator;
+        h[0] += word(0) & 0x3ffffff;
+        h[1] += (word(3) >> 2) & 0x3ffffff;
+        h[2] += (word(6) >> 4) & 0x3ffffff;
+        h[3] += (word(9) >> 6) & 0x3ffffff;
+        h[4] += (word(12) >> 8) | (1 << 24);
+
+        // h * r mod 2^130 - 5. Limbs that wrap past 2^130 come back around multiplied by 5
+        let r: [u64; 5] = self.r.map(u64::from);
+        let r5: [u64; 5] = r.map(|limb| limb * 5);
+        let h64: [u64; 5] = h.map(u64::from);
+        let d0 = h64[0] * r[0] + h64[1] * r5[4] + h64[2] * r5[3] + h64[3] * r5[2] + h64[4] * r5[1];
+        let mut d1 = h64[0] * r[1] + h64[1] * r[0] + h64[2] * r5[4] + h64[3] * r5[3] + h64[4] * r5[2];
+        let mut d2 = h64[0] * r[2] + h64[1] * r[1] + h64[2] * r[0] + h64[3] * r5[4] + h64[4] * r5[3];
+        let mut d3 = h64[0] * r[3] + h64[1] * r[2] + h64[2] * r[1] + h64[3] * r[0] + h64[4] * r5[4];
+        let mut d4 = h64[0] * r[4] + h64[1] * r[3] + h64[2] * r[2] + h64[3] * r[1] + h64[4] * r[0];
+
+        // Carry back down to 26 bits per limb
+        d1 += d0 >> 26;
+        d2 += d1 >> 26;
+        d3 += d2 >> 26;
+        d4 += d3 >> 26;
+        let mut h0: u64 = (d0 & 0x3ffffff) + (d4 >> 26) * 5;
+        let h1: u64 = (d1 & 0x3ffffff) + (h0 >> 26);
+        h0 &= 0x3ffffff;
+        *h = [h0 as u32, h1 as u32, (d2 & 0x3ffffff) as u32, (d3 & 0x3ffffff) as u32, (d4 & 0x3ffffff) as u32];
+    }
+
+    // The AEAD construction zero-pads each piece of input out to a whole block
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(16) {
+            let mut block: [u8; 16] = [0; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.block(&block);
+        }
+    }
+
+    fn finalize(self) -> [u8; TAG_BYTES] {
+        let mut h: [u32; 5] = self.accumulator;
+        // Fully carry
+        for ind in 1..5 {
+            h[ind] += h[ind - 1] >> 26;
+            h[ind - 1] &= 0x3ffffff;
+        }
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= 0x3ffffff;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+
+        // Compute h - p = h + 5 - 2^130, and pick it if it didn't go negative. Done with a
+        // mask instead of a branch so the timing doesn't depend on the tag
+        let mut g: [u32; 5] = [0; 5];
+        let mut carry: u32 = 5;
+        for ind in 0..5 {
+            let sum: u32 = h[ind] + carry;
+            g[ind] = sum & 0x3ffffff;
+            carry = sum >> 26;
+        }
+        let use_g: u32 = 0u32.wrapping_sub(carry);
+        for ind in 0..5 {
+            h[ind] = (h[ind] & !use_g) | (g[ind] & use_g);
+        }
+
+        // Back to four 32-bit words, then add s mod 2^128
+        let words: [u32; 4] = [
+            h[0] | (h[1] << 26),
+            (h[1] >> 6) | (h[2] << 20),
+            (h[2] >> 12) | (h[3] << 14),
+            (h[3] >> 18) | (h[4] << 8)
+        ];
+        let mut tag: [u8; TAG_BYTES] = [0; TAG_BYTES];
+        let mut carry: u64 = 0;
+        for ind in 0..4 {
+            let sum: u64 = words[ind] as u64 + self.s[ind] as u64 + carry;
+            tag[(ind * 4)..(ind * 4 + 4)].copy_from_slice(&(sum as u32).to_le_bytes());
+            carry = sum >> 32;
+        }
+        tag
+    }
+}
+
+// The tag covers the associated data and ciphertext, each padded to 16 bytes, then both lengths
+fn compute_tag(key: &[u8; KEY_BYTES], nonce: &[u8; NONCE_BYTES], associated_data: &[u8], ciphertext: &[u8]) -> [u8; TAG_BYTES] {
+    // "The one-time key is generated from the first 32 bytes of the ChaCha20 block with counter 0"
+    let mut one_time_key: [u8; 32] = [0; 32];
+    one_time_key.copy_from_slice(&chacha20_block(key, 0, nonce)[..32]);
+
+    let mut poly: Poly1305 = Poly1305::new(&one_time_key);
+    poly.update_padded(associated_data);
+    poly.update_padded(ciphertext);
+    let mut lengths: [u8; 16] = [0; 16];
+    lengths[..8].copy_from_slice(&(associated_data.len() as u64).to_le_bytes());
+    lengths[8..].copy_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly.update_padded(&lengths);
+    poly.finalize()
+}
+
+/// Encrypt the plaintext, returning the ciphertext with the 16 byte tag appended.
+/// A nonce must never be used twice with the same key
+pub fn seal(key: &[u8; KEY_BYTES], nonce: &[u8; NONCE_BYTES], associated_data: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = plaintext.to_vec();
+    // The keystream starts at block 1, since block 0 made the Poly1305 key
+    chacha20_xor(key, 1, nonce, &mut output);
+    let tag: [u8; TAG_BYTES] = compute_tag(key, nonce, associated_data, &output);
+    output.extend_from_slice(&tag);
+    output
+}
+
+/// Check the tag and decrypt. Nothing is decrypted unless the tag matches
+pub fn open(key: &[u8; KEY_BYTES], nonce: &[u8; NONCE_BYTES], associated_data: &[u8], sealed: &[u8]) -> Result<Vec<u8>, AuthenticationFailed> {
+    if sealed.len() < TAG_BYTES { return Err(AuthenticationFailed); }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_BYTES);
+
+    // Compare every byte, so the time taken doesn't leak how much of the tag was right
+    let expected: [u8; TAG_BYTES] = compute_tag(key, nonce, associated_data, ciphertext);
+    let difference: u8 = expected.iter().zip(tag.iter()).fold(0, |acc, (a, b)| acc | (a ^ b));
+    if difference != 0 { return Err(AuthenticationFailed); }
+
+    let mut plaintext: Vec<u8> = ciphertext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut plaintext);
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(|byte| byte.is_ascii_hexdigit()).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    // RFC 8439 section 2.8.2
+    const PLAINTEXT: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    fn rfc_key() -> [u8; KEY_BYTES] {
+        core::array::from_fn(|index| 0x80 + index as u8)
+    }
+    const NONCE: [u8; NONCE_BYTES] = [0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47];
+    const ASSOCIATED_DATA: [u8; 12] = [0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7];
+
+    #[test]
+    fn seals_the_rfc_8439_vector() {
+        let expected: Vec<u8> = hex("
+            d3 1a 8d 34 64 8e 60 db 7b 86 af bc 53 ef 7e c2
+            a4 ad ed 51 29 6e 08 fe a9 e2 b5 a7 36 ee 62 d6
+            3d be a4 5e 8c a9 67 12 82 fa fb 69 da 92 72 8b
+            1a 71 de 0a 9e 06 0b 29 05 d6 a5 b6 7e cd 3b 36
+            92 dd bd 7f 2d 77 8b 8c 98 03 ae e3 28 09 1b 58
+            fa b3 24 e4 fa d6 75 94 55 85 80 8b 48 31 d7 bc
+            3f f4 de f0 8e 4b 7a 9d e5 76 d2 65 86 ce c6 4b
+            61 16
+            1a e1 0b 59 4f 09 e2 6a 7e 90 2e cb d0 60 06 91");
+        let sealed: Vec<u8> = seal(&rfc_key(), &NONCE, &ASSOCIATED_DATA, PLAINTEXT);
+        assert_eq!(sealed, expected);
+        assert_eq!(open(&rfc_key(), &NONCE, &ASSOCIATED_DATA, &sealed).unwrap(), PLAINTEXT);
+    }
+
+    #[test]
+    fn tampering_fails_to_open() {
+        let sealed: Vec<u8> = seal(&rfc_key(), &NONCE, &ASSOCIATED_DATA, PLAINTEXT);
+        // Every bit of the tag and the ciphertext counts
+        for index in [0, PLAINTEXT.len() - 1, sealed.len() - TAG_BYTES, sealed.len() - 1] {
+            let mut tampered: Vec<u8> = sealed.clone();
+            tampered[index] ^= 1;
+            assert_eq!(open(&rfc_key(), &NONCE, &ASSOCIATED_DATA, &tampered), Err(AuthenticationFailed));
+        }
+        // So does the associated data, and a tag can't be cut short
+        assert_eq!(open(&rfc_key(), &NONCE, &ASSOCIATED_DATA[1..], &sealed), Err(AuthenticationFailed));
+        assert_eq!(open(&rfc_key(), &NONCE, &ASSOCIATED_DATA, &sealed[..TAG_BYTES - 1]), Err(AuthenticationFailed));
+    }
+}
@@ -0,0 +1,238 @@
+/* Just enough ASN.1 DER to read and write RSA keys: INTEGER, BIT STRING, OCTET STRING, NULL,
+    OBJECT IDENTIFIER and SEQUENCE. Everything is checked strictly -- lengths and integers must use
+    their shortest encoding and nothing may follow the outermost value -- since we read keys from peers. */
+
+use crate::keygen::{ self, Key };
+
+/// The universal tag for INTEGER
+pub const TAG_INTEGER: u8 = 0x02;
+/// The universal tag for BIT STRING
+pub const TAG_BIT_STRING: u8 = 0x03;
+/// The universal tag for OCTET STRING
+pub const TAG_OCTET_STRING: u8 = 0x04;
+/// The universal tag for NULL
+pub const TAG_NULL: u8 = 0x05;
+/// The universal tag for OBJECT IDENTIFIER
+pub const TAG_OBJECT_IDENTIFIER: u8 = 0x06;
+/// The tag for SEQUENCE, with the constructed bit set
+pub const TAG_SEQUENCE: u8 = 0x30;
+
+/// 1.2.840.113549.1.1.1, already encoded
+pub const OID_RSA_ENCRYPTION: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// Why some DER couldn't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerError {
+    /// Ran out of input in the middle of a value
+    Truncated,
+    /// A value with some other tag where a particular one should be
+    UnexpectedTag {
+        /// The tag that should have been there
+        expected: u8,
+        /// The tag that was
+        found: u8
+    },
+    /// A length that's indefinite, not in its shortest form, or absurdly long
+    BadLength,
+    /// An integer that isn't in its shortest form
+    NonMinimalInteger,
+    /// We only deal in non-negative integers
+    NegativeInteger,
+    /// An integer too big for a Key, or a u64 for small integers
+    IntegerTooLarge,
+    /// A BIT STRING with unused bits, which keys never have
+    BadBitString,
+    /// A NULL with contents
+    BadNull,
+    /// Input left over after the value
+    TrailingData
+}
+
+/// Builds up a DER encoding one value at a time
+pub struct DerWriter {
+    buffer: Vec<u8>
+}
+impl Default for DerWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl DerWriter {
+    /// An empty writer
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+    /// The encoding of everything written so far
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    fn write_length(&mut self, length: usize) {
+        if length < 0x80 {
+            self.buffer.push(length as u8);
+            return;
+        }
+        // Long form: the number of length bytes, then the length in big endian
+        let bytes = length.to_be_bytes();
+        let skip: usize = bytes.iter().take_while(|byte| **byte == 0).count();
+        self.buffer.push(0x80 | (bytes.len() - skip) as u8);
+        self.buffer.extend_from_slice(&bytes[skip..]);
+    }
+    /// Write a value with any tag, given its encoded contents
+    pub fn write_tlv(&mut self, tag: u8, contents: &[u8]) {
+        self.buffer.push(tag);
+        self.write_length(contents.len());
+        self.buffer.extend_from_slice(contents);
+    }
+
+    /// Write a non-negative Key as an INTEGER
+    pub fn write_integer(&mut self, value: &Key) {
+        assert!(!value.is_negative());
+        let bytes = keygen::key_to_be_bytes(value);
+        let skip: usize = bytes.iter().take_while(|byte| **byte == 0).count();
+        let mut contents: Vec<u8> = Vec::with_capacity(bytes.len() - skip + 1);
+        // INTEGERs are two's complement, so a set top bit needs a zero byte in front.
+        // Zero itself is a single zero byte
+        if skip == bytes.len() || bytes[skip] & 0x80 != 0 { contents.push(0); }
+        contents.extend_from_slice(&bytes[skip..]);
+        self.write_tlv(TAG_INTEGER, &contents);
+    }
+    /// Write a u64 as an INTEGER
+    pub fn write_small_integer(&mut self, value: u64) {
+        self.write_integer(&Key::from(value));
+    }
+    /// Write an empty NULL
+    pub fn write_null(&mut self) {
+        self.write_tlv(TAG_NULL, &[]);
+    }
+    /// Write an OBJECT IDENTIFIER that's already encoded, like OID_RSA_ENCRYPTION
+    pub fn write_oid(&mut self, encoded: &[u8]) {
+        self.write_tlv(TAG_OBJECT_IDENTIFIER, encoded);
+    }
+    /// Write an OCTET STRING
+    pub fn write_octet_string(&mut self, contents: &[u8]) {
+        self.write_tlv(TAG_OCTET_STRING, contents);
+    }
+    /// We only ever need whole bytes, so there are never unused bits
+    pub fn write_bit_string(&mut self, contents: &[u8]) {
+        let mut bits: Vec<u8> = Vec::with_capacity(contents.len() + 1);
+        bits.push(0);
+        bits.extend_from_slice(contents);
+        self.write_tlv(TAG_BIT_STRING, &bits);
+    }
+    /// Write a SEQUENCE with whatever the closure writes inside it
+    pub fn write_sequence<F: FnOnce(&mut DerWriter)>(&mut self, write_contents: F) {
+        let mut inner: DerWriter = DerWriter::new();
+        write_contents(&mut inner);
+        self.write_tlv(TAG_SEQUENCE, &inner.buffer);
+    }
+}
+
+/// Reads DER values one at a time from the front of a buffer
+pub struct DerReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+impl<'a> DerReader<'a> {
+    /// Start reading at the beginning of the data
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+    /// Whether everything has been read
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+    /// Make sure everything was read
+    pub fn finish(&self) -> Result<(), DerError> {
+        if self.is_empty() { Ok(()) } else { Err(DerError::TrailingData) }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, DerError> {
+        let byte: u8 = *self.data.get(self.position).ok_or(DerError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+    fn read_length(&mut self) -> Result<usize, DerError> {
+        let first: u8 = self.read_byte()?;
+        if first < 0x80 { return Ok(first as usize); }
+
+        let count: usize = (first & 0x7f) as usize;
+        // 0x80 is the BER indefinite length, which DER forbids. Nothing we read comes close to 4GB
+        if count == 0 || count > 4 { return Err(DerError::BadLength); }
+        let mut length: usize = 0;
+        for _ in 0..count {
+            length = (length << 8) | self.read_byte()? as usize;
+        }
+        // The long form is only allowed when the short form can't hold the length,
+        // and without leading zero bytes
+        if length < 0x80 || length >> ((count - 1) * 8) == 0 { return Err(DerError::BadLength); }
+        Ok(length)
+    }
+    /// The tag of the next value, without reading it
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.get(self.position).copied()
+    }
+    /// Read a value with the given tag and return its contents
+    pub fn read_tlv(&mut self, tag: u8) -> Result<&'a [u8], DerError> {
+        let found: u8 = self.read_byte()?;
+        if found != tag { return Err(DerError::UnexpectedTag { expected: tag, found }); }
+        let length: usize = self.read_length()?;
+        if self.data.len() - self.position < length { return Err(DerError::Truncated); }
+
+        let contents: &'a [u8] = &self.data[self.position..(self.position + length)];
+        self.position += length;
+        Ok(contents)
+    }
+    /// Skip whatever value comes next, no matter its tag
+    pub fn skip(&mut self) -> Result<(), DerError> {
+        let tag: u8 = self.peek_tag().ok_or(DerError::Truncated)?;
+        self.read_tlv(tag)?;
+        Ok(())
+    }
+
+    /// Read a non-negative INTEGER into a Key
+    pub fn read_integer(&mut self) -> Result<Key, DerError> {
+        let contents: &[u8] = self.read_tlv(TAG_INTEGER)?;
+        if contents.is_empty() { return Err(DerError::Truncated); }
+        if contents[0] & 0x80 != 0 { return Err(DerError::NegativeInteger); }
+        // A leading zero byte is only allowed to keep the next byte's top bit from reading as a sign
+        if contents.len() > 1 && contents[0] == 0 && contents[1] & 0x80 == 0 { return Err(DerError::NonMinimalInteger); }
+
+        // Keys are signed, so the value also has to leave the top bit of a Key clear
+        let magnitude: &[u8] = if contents[0] == 0 && contents.len() > 1 { &contents[1..] } else { contents };
+        if magnitude.len() > Key::BYTES as usize || (magnitude.len() == Key::BYTES as usize && magnitude[0] & 0x80 != 0) {
+            return Err(DerError::IntegerTooLarge);
+        }
+        // The contents are already two's complement, which is what from_be_slice expects
+        Key::from_be_slice(contents).ok_or(DerError::IntegerTooLarge)
+    }
+    /// Read a non-negative INTEGER that fits in a u64, like a version number
+    pub fn read_small_integer(&mut self) -> Result<u64, DerError> {
+        let value: Key = self.read_integer()?;
+        u64::try_from(value).map_err(|_| DerError::IntegerTooLarge)
+    }
+    /// Read a NULL, which has to be empty
+    pub fn read_null(&mut self) -> Result<(), DerError> {
+        if self.read_tlv(TAG_NULL)?.is_empty() { Ok(()) } else { Err(DerError::BadNull) }
+    }
+    /// Read an OBJECT IDENTIFIER, still encoded
+    pub fn read_oid(&mut self) -> Result<&'a [u8], DerError> {
+        self.read_tlv(TAG_OBJECT_IDENTIFIER)
+    }
+    /// Read an OCTET STRING
+    pub fn read_octet_string(&mut self) -> Result<&'a [u8], DerError> {
+        self.read_tlv(TAG_OCTET_STRING)
+    }
+    /// Read a BIT STRING made of whole bytes
+    pub fn read_bit_string(&mut self) -> Result<&'a [u8], DerError> {
+        let contents: &'a [u8] = self.read_tlv(TAG_BIT_STRING)?;
+        match contents.first() {
+            Some(0) => Ok(&contents[1..]),
+            _ => Err(DerError::BadBitString)
+        }
+    }
+    /// Read a SEQUENCE and return a reader over its contents
+    pub fn read_sequence(&mut self) -> Result<DerReader<'a>, DerError> {
+        Ok(DerReader::new(self.read_tlv(TAG_SEQUENCE)?))
+    }
+}
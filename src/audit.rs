@@ -0,0 +1,364 @@
+/* Auditing RSA moduli we get from peers for the weaknesses that let them be factored.
+    Each check only catches one kind of weak key, so a clean audit doesn't prove a modulus is strong:
+    - the small prime table, for moduli with a tiny factor
+    - Fermat's method, for primes that are too close together (|p - q| small next to n^(1/4))
+    - Pollard's rho, for a factor small enough to find in about sqrt(p) steps
+    - Pollard's p - 1, for a prime p where p - 1 only has small factors
+    - GCDs between every pair of moduli, for keys that share a prime because of a broken RNG
+    The work each check does is bounded by AuditLimits, so auditing untrusted keys can't hang. */
+
+use std::fmt;
+use std::fmt::Display;
+
+use crate::keygen::{ self, Key, MAX_MODULUS_BITS, bigmod, gcd, isqrt, passes_baillie_psw, rem_u64_batch };
+use crate::modular::MontgomeryContext;
+use crate::primes;
+
+/// How a factor was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Trial division by the small prime table
+    SmallPrime,
+    /// Fermat's method, for primes that are too close together
+    Fermat,
+    /// Pollard's rho, for a prime that's too small
+    PollardRho,
+    /// Pollard's p - 1, for a prime p where p - 1 has only small factors
+    PollardPMinusOne,
+    /// Shared with the modulus at this index in the audited set
+    SharedPrime(usize)
+}
+
+/// Factored holds the factor in a Box, so the other variants aren't the size of a Key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Weakness {
+    /// The modulus is prime, so anyone can work out the private exponent from n - 1
+    Prime,
+    /// A prime factor, and how it was found
+    Factored {
+        /// One of the primes. The other is the modulus divided by it
+        factor: Box<Key>,
+        /// How it was found
+        method: Method
+    },
+    /// The modulus at this index in the audited set is the same, so either private key decrypts both
+    SameModulus(usize)
+}
+
+/// How much work each factoring method gets per modulus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditLimits {
+    /// Steps of Fermat's method. A single step is enough when |p - q| < 2n^(1/4)
+    pub fermat_steps: u64,
+    /// Steps of Pollard's rho. Finds factors of up to about 2 log2(steps) bits
+    pub rho_steps: u64,
+    /// Pollard's p - 1 finds p when every prime power dividing p - 1 is at most this
+    pub p_minus_one_bound: u64
+}
+impl AuditLimits {
+    /// A few seconds per 2048-bit modulus
+    pub const DEFAULT: Self = Self { fermat_steps: 10_000, rho_steps: 20_000, p_minus_one_bound: 50_000 };
+}
+
+// One of the factoring methods, which gives up once it's taken as many steps as the limits allow
+type FactorCheck = fn(Key, &AuditLimits) -> Result<Option<Key>, keygen::Error>;
+
+/// Look for a way to factor a single modulus
+pub fn audit_modulus(modulus: Key, limits: &AuditLimits) -> Result<Option<Weakness>, keygen::Error> {
+    if modulus <= Key::ONE || modulus.bits() > MAX_MODULUS_BITS { return Err(keygen::Error::InvalidModulus); }
+
+    if let Some(factor) = small_prime_factor(modulus) {
+        if factor == modulus { return Ok(Some(Weakness::Prime)); }
+        return Ok(Some(Weakness::Factored { factor: Box::new(factor), method: Method::SmallPrime }));
+    }
+    if passes_baillie_psw(modulus)? { return Ok(Some(Weakness::Prime)); }
+
+    let checks: [(Method, FactorCheck); 3] = [
+        (Method::Fermat, fermat_factor),
+        (Method::PollardRho, pollard_rho_factor),
+        (Method::PollardPMinusOne, pollard_p_minus_one_factor)
+    ];
+    for (method, check) in checks {
+        if let Some(factor) = check(modulus, limits)? {
+            return Ok(Some(Weakness::Factored { factor: Box::new(factor), method }));
+        }
+    }
+    Ok(None)
+}
+
+/// Audit every modulus on its own, then look for moduli that share a prime. Finding the shared
+/// primes with a product tree would be faster for big sets, but the product of all the moduli
+/// doesn't fit in a Key, so this takes the GCD of every pair instead
+pub fn audit_moduli(moduli: &[Key], limits: &AuditLimits) -> Vec<Result<Option<Weakness>, keygen::Error>> {
+    let mut results: Vec<Result<Option<Weakness>, keygen::Error>> =
+        moduli.iter().map(|modulus| audit_modulus(*modulus, limits)).collect();
+
+    for first in 0..moduli.len() {
+        for second in (first + 1)..moduli.len() {
+            if results[first].is_err() || results[second].is_err() { continue; }
+
+            let (first_weakness, second_weakness) = if moduli[first] == moduli[second] {
+                (Weakness::SameModulus(second), Weakness::SameModulus(first))
+            }
+            else {
+                let factor: Key = gcd(moduli[first], moduli[second]);
+                if factor == Key::ONE { continue; }
+                (
+                    Weakness::Factored { factor: Box::new(factor), method: Method::SharedPrime(second) },
+                    Weakness::Factored { factor: Box::new(factor), method: Method::SharedPrime(first) }
+                )
+            };
+            // Keep whatever was found first for each modulus
+            if let Ok(result @ None) = &mut results[first] { *result = Some(first_weakness); }
+            if let Ok(result @ None) = &mut results[second] { *result = Some(second_weakness); }
+        }
+    }
+    results
+}
+
+// The smallest prime factor of the modulus, if it's 2 or in the small prime table
+fn small_prime_factor(modulus: Key) -> Option<Key> {
+    if (modulus & Key::ONE) == Key::ZERO { return Some(Key::TWO); }
+
+    let mut remainders: [u64; primes::PRIME_PRODUCT_COUNT] = [0; primes::PRIME_PRODUCT_COUNT];
+    rem_u64_batch(modulus, &primes::PRIME_PRODUCTS, &mut remainders);
+    for (product, remainder) in primes::PRIME_PRODUCTS.into_iter().zip(remainders) {
+        let common: u64 = primes::gcd_u64(remainder, product);
+        if common == 1 { continue; }
+        // remainder is zero if the modulus is the whole product, so look at the product's own primes
+        let prime: u64 = primes::FIRST_PRIMES.into_iter().find(|prime| common.is_multiple_of(*prime))?;
+        return Some(Key::from(prime));
+    }
+    None
+}
+
+// Fermat's method: look for n = a^2 - b^2 = (a - b)(a + b), starting from a = ceil(sqrt(n)).
+// b^2 is updated as a goes up, so each step only needs a square root when b^2 could be a square
+fn fermat_factor(modulus: Key, limits: &AuditLimits) -> Result<Option<Key>, keygen::Error> {
+    let mut a: Key = isqrt(modulus);
+    if a * a < modulus { a += Key::ONE; }
+    let mut b_squared: Key = a * a - modulus;
+
+    for _step in 0..limits.fermat_steps {
+        // Squares are 0, 1, 4 or 9 (mod 16)
+        let low_bits: Key = b_squared & Key::from(15u8);
+        if low_bits == Key::ZERO || low_bits == Key::ONE || low_bits == Key::FOUR || low_bits == Key::NINE {
+            let b: Key = isqrt(b_squared);
+            if b * b == b_squared {
+                let factor: Key = a - b;
+                if factor > Key::ONE && factor < modulus { return Ok(Some(factor)); }
+            }
+        }
+        // (a + 1)^2 - a^2 = 2a + 1
+        b_squared += (a << 1u32) + Key::ONE;
+        a += Key::ONE;
+    }
+    Ok(None)
+}
+
+// How many rho steps go into each product before taking a GCD
+const RHO_BATCH: u64 = 128;
+
+// Pollard's rho with Brent's cycle finding, multiplying the differences together so there's one
+// GCD per batch instead of one per step. Tries a few polynomials in case one cycles mod n itself.
+// Everything stays in Montgomery form: xR - yR = (x - y)R, and R is coprime to n, so the GCDs don't change
+fn pollard_rho_factor(modulus: Key, limits: &AuditLimits) -> Result<Option<Key>, keygen::Error> {
+    // Small prime factors are already ruled out, so the modulus is odd
+    let context: MontgomeryContext = MontgomeryContext::new(modulus)?;
+    let mut steps_left: u64 = limits.rho_steps;
+    for constant in [1u8, 3, 5] {
+        let constant: Key = context.to_montgomery(Key::from(constant));
+        let step = |x: Key| -> Key {
+            let next: Key = context.square(x) + constant;
+            if next >= modulus { next - modulus } else { next }
+        };
+
+        let mut y: Key = context.to_montgomery(Key::TWO);
+        let mut x: Key = y;
+        let mut saved: Key = y;
+        let mut product: Key = context.one();
+        let mut common: Key = Key::ONE;
+        let mut cycle_length: u64 = 1;
+        while common == Key::ONE && steps_left > 0 {
+            x = y;
+            for _ in 0..cycle_length { y = step(y); }
+            let mut done: u64 = 0;
+            while done < cycle_length && common == Key::ONE && steps_left > 0 {
+                saved = y;
+                let batch: u64 = RHO_BATCH.min(cycle_length - done).min(steps_left);
+                for _ in 0..batch {
+                    y = step(y);
+                    product = context.mul(product, (x - y).abs());
+                }
+                common = gcd(product, modulus);
+                done += batch;
+                steps_left -= batch;
+            }
+            cycle_length <<= 1;
+        }
+
+        // The batch overshot and multiplied every factor in, so redo it one step at a time
+        if common == modulus {
+            common = Key::ONE;
+            while common == Key::ONE {
+                saved = step(saved);
+                common = gcd((x - saved).abs(), modulus);
+            }
+        }
+        if common != Key::ONE && common != modulus { return Ok(Some(common)); }
+        if steps_left == 0 { break; }
+    }
+    Ok(None)
+}
+
+// How many primes go into the power before taking a GCD
+const P_MINUS_ONE_BATCH: usize = 64;
+
+// Pollard's p - 1, stage one. If p - 1 divides M = the product of every prime power up to the
+// bound, then 2^M = 1 (mod p), so p divides gcd(2^M - 1, n)
+fn pollard_p_minus_one_factor(modulus: Key, limits: &AuditLimits) -> Result<Option<Key>, keygen::Error> {
+    let bound: u64 = limits.p_minus_one_bound;
+    let mut power: Key = Key::TWO;
+    let mut checkpoint: Key = power;
+
+    let mut sieve = primes::primes_in(2..bound.saturating_add(1)).peekable();
+    while let Some(&batch_start) = sieve.peek() {
+        for prime in sieve.by_ref().take(P_MINUS_ONE_BATCH) {
+            let mut prime_power: u64 = prime;
+            while prime_power <= bound / prime { prime_power *= prime; }
+            power = bigmod(power, Key::from(prime_power), modulus)?;
+        }
+
+        let common: Key = gcd(power - Key::ONE, modulus);
+        if common == modulus {
+            // Every prime factor showed up in the same batch. Go back and take it one prime at a time
+            return p_minus_one_one_by_one(modulus, checkpoint, batch_start, bound);
+        }
+        if common != Key::ONE { return Ok(Some(common)); }
+        checkpoint = power;
+    }
+    Ok(None)
+}
+// Redo a batch from its first prime, starting from the power before it
+fn p_minus_one_one_by_one(modulus: Key, mut power: Key, batch_start: u64, bound: u64) -> Result<Option<Key>, keygen::Error> {
+    for prime in primes::primes_in(batch_start..bound.saturating_add(1)) {
+        let mut prime_power: u64 = prime;
+        while prime_power <= bound / prime { prime_power *= prime; }
+        let next: Key = bigmod(power, Key::from(prime_power), modulus)?;
+        let common: Key = gcd(next - Key::ONE, modulus);
+        if common == modulus { return Ok(None); }
+        if common != Key::ONE { return Ok(Some(common)); }
+        power = next;
+    }
+    Ok(None)
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Method::SmallPrime => write!(f, "small prime table"),
+            Method::Fermat => write!(f, "Fermat's method"),
+            Method::PollardRho => write!(f, "Pollard's rho"),
+            Method::PollardPMinusOne => write!(f, "Pollard's p - 1"),
+            Method::SharedPrime(other) => write!(f, "prime shared with key {}", other)
+        }
+    }
+}
+impl Display for Weakness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Weakness::Prime => write!(f, "modulus is prime"),
+            Weakness::Factored { factor, method } => write!(f, "factored by {}: {}", method, factor),
+            Weakness::SameModulus(other) => write!(f, "same modulus as key {}", other)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keygen::NumberHandler;
+    use rand::Rng;
+
+    fn next_prime(start: Key) -> Key {
+        let mut candidate: Key = start | Key::ONE;
+        while !passes_baillie_psw(candidate).unwrap() {
+            candidate += Key::TWO;
+        }
+        candidate
+    }
+    // A prime p where p - 1 is 2 * extra times some of the odd primes below 100, each at most once,
+    // so p - 1 divides the exponent stage one builds from the first batch of primes
+    fn smooth_prime(extra: u64) -> Key {
+        let mut rng = rand::rng();
+        loop {
+            let mut candidate: Key = Key::from(2 * extra);
+            for prime in primes::FIRST_PRIMES.iter().take_while(|prime| **prime < 100) {
+                if rng.random::<bool>() { candidate *= Key::from(*prime); }
+            }
+            candidate += Key::ONE;
+            if candidate.bits() >= 48 && passes_baillie_psw(candidate).unwrap() { return candidate; }
+        }
+    }
+    fn assert_factor(factor: Option<Key>, modulus: Key) {
+        let factor: Key = factor.expect("no factor found");
+        assert!(factor > Key::ONE && factor < modulus && modulus % factor == Key::ZERO);
+    }
+
+    #[test]
+    fn fermat_finds_close_primes() {
+        let mut handler = NumberHandler::new(32).unwrap();
+        let prime_a: Key = handler.get_random_n_bit_prime(256).unwrap();
+        let prime_b: Key = next_prime(prime_a + (Key::ONE << 20u32));
+        let modulus: Key = prime_a * prime_b;
+        assert_factor(fermat_factor(modulus, &AuditLimits::DEFAULT).unwrap(), modulus);
+        assert_eq!(audit_modulus(modulus, &AuditLimits::DEFAULT), Ok(Some(Weakness::Factored { factor: Box::new(prime_a), method: Method::Fermat })));
+    }
+
+    #[test]
+    fn rho_finds_a_small_factor() {
+        let mut handler = NumberHandler::new(32).unwrap();
+        let small: Key = handler.get_random_n_bit_prime(30).unwrap();
+        let modulus: Key = small * handler.get_random_n_bit_prime(400).unwrap();
+        let limits: AuditLimits = AuditLimits { rho_steps: 1_000_000, ..AuditLimits::DEFAULT };
+        assert_eq!(pollard_rho_factor(modulus, &limits), Ok(Some(small)));
+    }
+
+    #[test]
+    fn p_minus_one_finds_a_smooth_prime() {
+        let mut handler = NumberHandler::new(32).unwrap();
+        let smooth: Key = smooth_prime(1);
+        let modulus: Key = smooth * handler.get_random_n_bit_prime(256).unwrap();
+        assert_eq!(pollard_p_minus_one_factor(modulus, &AuditLimits::DEFAULT), Ok(Some(smooth)));
+
+        // Both primes come out in the first batch, which has 311 in it, so it has to be redone one
+        // prime at a time to get them apart
+        let other: Key = smooth_prime(311);
+        let modulus: Key = smooth * other;
+        assert_eq!(pollard_p_minus_one_factor(modulus, &AuditLimits::DEFAULT), Ok(Some(smooth)));
+    }
+
+    #[test]
+    fn finds_shared_primes_and_moduli() {
+        let mut handler = NumberHandler::new(32).unwrap();
+        let primes: Vec<Key> = (0..5).map(|_| handler.get_random_n_bit_prime(256).unwrap()).collect();
+        let moduli: [Key; 4] = [primes[0] * primes[1], primes[2] * primes[3], primes[0] * primes[4], primes[2] * primes[3]];
+        // Low enough that the single-modulus checks don't find anything on their own
+        let limits: AuditLimits = AuditLimits { fermat_steps: 10, rho_steps: 10, p_minus_one_bound: 10 };
+
+        let results: Vec<Result<Option<Weakness>, keygen::Error>> = audit_moduli(&moduli, &limits);
+        assert_eq!(results[0], Ok(Some(Weakness::Factored { factor: Box::new(primes[0]), method: Method::SharedPrime(2) })));
+        assert_eq!(results[2], Ok(Some(Weakness::Factored { factor: Box::new(primes[0]), method: Method::SharedPrime(0) })));
+        assert_eq!(results[1], Ok(Some(Weakness::SameModulus(3))));
+        assert_eq!(results[3], Ok(Some(Weakness::SameModulus(1))));
+    }
+
+    #[test]
+    fn reports_primes_and_small_factors() {
+        let prime: Key = next_prime(Key::ONE << 200u32);
+        assert_eq!(audit_modulus(prime, &AuditLimits::DEFAULT), Ok(Some(Weakness::Prime)));
+        assert_eq!(audit_modulus(prime * Key::SEVEN, &AuditLimits::DEFAULT),
+            Ok(Some(Weakness::Factored { factor: Box::new(Key::SEVEN), method: Method::SmallPrime })));
+        assert_eq!(audit_modulus(Key::ONE, &AuditLimits::DEFAULT), Err(keygen::Error::InvalidModulus));
+    }
+}
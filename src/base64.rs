@@ -0,0 +1,80 @@
+// Standard base64 (RFC 4648, with + and /), as used by PEM files and OpenSSH keys
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Why some text couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base64Error {
+    /// A byte that isn't in the alphabet
+    InvalidCharacter(u8),
+    /// The input stops partway through a group, or has padding in the wrong place
+    BadPadding
+}
+
+/// Encode, with or without the trailing '=' padding
+pub fn encode_with_padding(input: &[u8], pad: bool) -> String {
+    let mut output: String = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let group: u32 = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        // Three bytes make four characters. A short chunk makes one more character than it has bytes
+        for ind in 0..(chunk.len() + 1) {
+            output.push(ALPHABET[((group >> (18 - 6 * ind)) & 0x3f) as usize] as char);
+        }
+        if pad {
+            for _ in chunk.len()..3 { output.push('='); }
+        }
+    }
+    output
+}
+/// Encode with padding, which is what PEM and OpenSSH want
+pub fn encode(input: &[u8]) -> String {
+    encode_with_padding(input, true)
+}
+
+fn decode_character(character: u8) -> Result<u32, Base64Error> {
+    match character {
+        b'A'..=b'Z' => Ok((character - b'A') as u32),
+        b'a'..=b'z' => Ok((character - b'a' + 26) as u32),
+        b'0'..=b'9' => Ok((character - b'0' + 52) as u32),
+        b'+' => Ok(62),
+        b'/' => Ok(63),
+        _ => Err(Base64Error::InvalidCharacter(character))
+    }
+}
+/// Decode, skipping any whitespace (PEM bodies are split into lines). Padding is optional
+pub fn decode(input: &str) -> Result<Vec<u8>, Base64Error> {
+    let mut output: Vec<u8> = Vec::with_capacity(input.len() / 4 * 3);
+    let mut group: u32 = 0;
+    let mut count: usize = 0;
+    let mut padding: usize = 0;
+
+    for character in input.bytes() {
+        if character.is_ascii_whitespace() { continue; }
+        if character == b'=' {
+            padding += 1;
+            continue;
+        }
+        // Nothing but more padding may follow padding
+        if padding > 0 { return Err(Base64Error::BadPadding); }
+
+        group = (group << 6) | decode_character(character)?;
+        count += 1;
+        if count == 4 {
+            output.extend_from_slice(&group.to_be_bytes()[1..]);
+            group = 0;
+            count = 0;
+        }
+    }
+
+    // A final group of 2 or 3 characters holds 1 or 2 bytes
+    match count {
+        0 => if padding != 0 { return Err(Base64Error::BadPadding); },
+        2 => output.push((group >> 4) as u8),
+        3 => output.extend_from_slice(&((group >> 2) as u16).to_be_bytes()),
+        _ => return Err(Base64Error::BadPadding)
+    }
+    if count != 0 && padding != 0 && count + padding != 4 { return Err(Base64Error::BadPadding); }
+    Ok(output)
+}
@@ -0,0 +1,203 @@
+/* Key fingerprints, so operators can check out of band that they're talking to the right server.
+    The fingerprint is SHA-256 over the OpenSSH wire encoding of the public key, which makes it match
+    what ssh-keygen -l prints for the same key. It can be shown as hex, as base64, as a BIP-39 word
+    list that's easier to read out loud, or as OpenSSH's "drunken bishop" randomart picture. */
+
+use crate::base64;
+use crate::hash;
+use crate::keygen::RSAPublicKey;
+use crate::openssh::SshPublicKey;
+
+// The BIP-39 English word list. Every word is unique in its first four letters
+static WORD_LIST: &str = include_str!("bip39_english.txt");
+const WORD_COUNT: usize = 2048;
+const BITS_PER_WORD: usize = 11;
+
+// The randomart field is 17 by 9, and each square shows how often the bishop landed there
+const FIELD_WIDTH: usize = 17;
+const FIELD_HEIGHT: usize = 9;
+// Squares visited more often get "heavier" characters. The last two mark the start and end
+const AUGMENTATION: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+/// A SHA-256 fingerprint of a public key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// SHA-256 of the key's OpenSSH blob
+    pub digest: [u8; 32]
+}
+impl Fingerprint {
+    /// Fingerprint of an OpenSSH public key blob
+    pub fn of_blob(blob: &[u8]) -> Self {
+        Self { digest: hash::sha256_bytes(blob) }
+    }
+
+    /// Lowercase hex bytes separated by colons
+    pub fn to_hex(&self) -> String {
+        self.digest.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(":")
+    }
+    /// The same format ssh-keygen -l uses, eg "SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8"
+    pub fn to_base64(&self) -> String {
+        format!("SHA256:{}", base64::encode_with_padding(&self.digest, false))
+    }
+
+    /// The digest as a BIP-39 mnemonic: 256 bits plus an 8 bit checksum (the first byte of the
+    /// digest's own SHA-256), split into 24 words of 11 bits each
+    pub fn to_words(&self) -> Vec<&'static str> {
+        let words: Vec<&'static str> = WORD_LIST.lines().collect();
+        debug_assert_eq!(words.len(), WORD_COUNT);
+
+        let mut bits: Vec<u8> = self.digest.to_vec();
+        bits.push(hash::sha256_bytes(&self.digest)[0]);
+        let word_count: usize = bits.len() * 8 / BITS_PER_WORD;
+
+        (0..word_count).map(|word| {
+            let mut index: usize = 0;
+            for bit in (word * BITS_PER_WORD)..((word + 1) * BITS_PER_WORD) {
+                index = (index << 1) | ((bits[bit >> 3] >> (7 - (bit & 7))) & 1) as usize;
+            }
+            words[index]
+        }).collect()
+    }
+
+    /// OpenSSH's randomart. A bishop starts in the middle of the field and moves diagonally two bits
+    /// of the digest at a time, lowest bits first. The title goes in the top border, eg "RSA 2048"
+    pub fn randomart(&self, title: &str) -> String {
+        let mut field: [[u8; FIELD_HEIGHT]; FIELD_WIDTH] = [[0; FIELD_HEIGHT]; FIELD_WIDTH];
+        let start_mark: u8 = (AUGMENTATION.len() - 2) as u8;
+        let end_mark: u8 = (AUGMENTATION.len() - 1) as u8;
+
+        let mut x: usize = FIELD_WIDTH / 2;
+        let mut y: usize = FIELD_HEIGHT / 2;
+        for byte in self.digest {
+            let mut input: u8 = byte;
+            for _ in 0..4 {
+                x = if input & 1 != 0 { (x + 1).min(FIELD_WIDTH - 1) } else { x.saturating_sub(1) };
+                y = if input & 2 != 0 { (y + 1).min(FIELD_HEIGHT - 1) } else { y.saturating_sub(1) };
+                // Stop counting before the counter runs into the start and end marks
+                if field[x][y] < start_mark - 1 {
+                    field[x][y] += 1;
+                }
+                input >>= 2;
+            }
+        }
+        field[FIELD_WIDTH / 2][FIELD_HEIGHT / 2] = start_mark;
+        field[x][y] = end_mark;
+
+        let mut art: String = border(title);
+        art.push('\n');
+        for row in 0..FIELD_HEIGHT {
+            art.push('|');
+            for column in 0..FIELD_WIDTH {
+                art.push(AUGMENTATION[field[column][row] as usize] as char);
+            }
+            art.push_str("|\n");
+        }
+        art.push_str(&border("SHA256"));
+        art
+    }
+}
+
+// A border line with a bracketed title centered in it, or no title if it doesn't fit
+fn border(title: &str) -> String {
+    let bracketed: String = if title.len() + 2 <= FIELD_WIDTH { format!("[{}]", title) } else { String::new() };
+    let left: usize = (FIELD_WIDTH - bracketed.len()) / 2;
+    format!("+{}{}{}+", "-".repeat(left), bracketed, "-".repeat(FIELD_WIDTH - left - bracketed.len()))
+}
+
+impl SshPublicKey {
+    /// The fingerprint ssh-keygen -l shows for this key
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of_blob(&self.to_blob())
+    }
+    /// The randomart titled with the key type and size, like ssh-keygen -lv shows it
+    pub fn randomart(&self) -> String {
+        let title: String = match self {
+            SshPublicKey::Rsa(key) => format!("RSA {}", key.shared.bits()),
+            SshPublicKey::Ed25519(_) => String::from("ED25519 256")
+        };
+        self.fingerprint().randomart(&title)
+    }
+}
+impl RSAPublicKey {
+    /// The fingerprint of the key as an ssh-rsa key
+    pub fn fingerprint(&self) -> Fingerprint {
+        SshPublicKey::Rsa(Box::new(*self)).fingerprint()
+    }
+    /// The randomart of the key as an ssh-rsa key
+    pub fn randomart(&self) -> String {
+        SshPublicKey::Rsa(Box::new(*self)).randomart()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The ssh-keygen keys from the openssh tests, and what ssh-keygen -lv prints for them
+    const RSA_PUBLIC: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQCxBbRqUHS977viFAUg6H4WfQl1njixWRHpMvgJVGBPIhorNsnbr0ncIan4R0mGcc3Yvrkv8DW/3nUB7cmXeiqGmbRDrpTZRBVg32UBw2YpUpFbH3rrU1MHvEOyQZ1I7TkD8ysqULO1WHfDPxzHIR4qc6bJIN9HpUKLx5ci98/eRw== test@example";
+    const ED25519_PUBLIC: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIFWCrQYSc/0nP4VfP89DLf+MZRbDLL1YWLvahM7fu33X test@example";
+    const RSA_RANDOMART: &str = "\
++---[RSA 1024]----+
+|    . oo.+o      |
+|   . o  . .      |
+|. o . .  =       |
+|.= o   ...o      |
+|+.o + . So.      |
+|+.o* + +.o.  .   |
+|o=..+ +oo.  . .  |
+|+ .o .... o  o E |
+| .+oo.o  . .. +  |
++----[SHA256]-----+";
+    const ED25519_RANDOMART: &str = "\
++--[ED25519 256]--+
+|*Bo.             |
+|==B.o            |
+|+=+= E           |
+|oo* .            |
+| = o .  S        |
+|. B .o .         |
+|oB += . .        |
+|++++.=..         |
+|+.o oo*+         |
++----[SHA256]-----+";
+
+    #[test]
+    fn matches_ssh_keygen() {
+        let (rsa, _) = SshPublicKey::from_openssh_line(RSA_PUBLIC).unwrap();
+        assert_eq!(rsa.fingerprint().to_base64(), "SHA256:St2amOuojSulINuBDJb8h2N5IB09RRXhM+mjdtt34Gc");
+        assert_eq!(rsa.fingerprint().to_hex(), "4a:dd:9a:98:eb:a8:8d:2b:a5:20:db:81:0c:96:fc:87:63:79:20:1d:3d:45:15:e1:33:e9:a3:76:db:77:e0:67");
+        assert_eq!(rsa.randomart(), RSA_RANDOMART);
+        let SshPublicKey::Rsa(public_key) = &rsa else { panic!("Expected an RSA key") };
+        assert_eq!(public_key.fingerprint(), rsa.fingerprint());
+
+        let (ed25519, _) = SshPublicKey::from_openssh_line(ED25519_PUBLIC).unwrap();
+        assert_eq!(ed25519.fingerprint().to_base64(), "SHA256:rqBghZujG1TSr1L+cuTPrhMq0gGNR2gLMDYfjLPQQN8");
+        assert_eq!(ed25519.fingerprint().to_hex(), "ae:a0:60:85:9b:a3:1b:54:d2:af:52:fe:72:e4:cf:ae:13:2a:d2:01:8d:47:68:0b:30:36:1f:8c:b3:d0:40:df");
+        assert_eq!(ed25519.randomart(), ED25519_RANDOMART);
+    }
+
+    #[test]
+    fn words_follow_bip_39() {
+        // The 256 bit entropy vectors from the BIP-39 reference implementation
+        let words = |byte: u8| Fingerprint { digest: [byte; 32] }.to_words().join(" ");
+        assert_eq!(words(0x00), format!("{}art", "abandon ".repeat(23)));
+        assert_eq!(words(0xff), format!("{}vote", "zoo ".repeat(23)));
+        assert_eq!(words(0x7f), "legal winner thank year wave sausage worth useful legal winner thank year wave \
+            sausage worth useful legal winner thank year wave sausage worth title");
+
+        // Turning the words back into bits gives the digest, then its checksum byte
+        let (rsa, _) = SshPublicKey::from_openssh_line(RSA_PUBLIC).unwrap();
+        let fingerprint: Fingerprint = rsa.fingerprint();
+        let word_list: Vec<&str> = WORD_LIST.lines().collect();
+        let words: Vec<&str> = fingerprint.to_words();
+        assert_eq!(words.len(), 24);
+        let mut bits: Vec<u8> = Vec::new();
+        for word in words {
+            let index: usize = word_list.iter().position(|entry| *entry == word).unwrap();
+            bits.extend((0..BITS_PER_WORD).rev().map(|bit| ((index >> bit) & 1) as u8));
+        }
+        let bytes: Vec<u8> = bits.chunks(8).map(|byte| byte.iter().fold(0, |total, bit| (total << 1) | bit)).collect();
+        assert_eq!(bytes[..32], fingerprint.digest);
+        assert_eq!(bytes[32], hash::sha256_bytes(&fingerprint.digest)[0]);
+    }
+}
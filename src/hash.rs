@@ -1,4 +1,4 @@
-/* Program to implement SHA-256 Hash. Quoted comments come from specification at https://helix.stormhub.org/papers/SHA-256.pdf */ 
+/* Program to implement SHA-256 Hash. Quoted comments come from specification at https://helix.stormhub.org/papers/SHA-256.pdf */
 
 // "The first 32 bits of the fractional parts of the cube roots of the first 64 prime numbers"
 static K: [u32; 64] = [
@@ -12,6 +12,12 @@ static K: [u32; 64] = [
     0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
 ];
 
+// "The first 32 bits of the fractional part of the square roots of the first 8 prime numbers"
+static INITIAL_HASHES: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+    0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19
+];
+
 // A circular rotate right, so eg rotate_right(0b00111000, 4) = 0b00000011
 fn rotate_right(word: u32, shift: u8) -> u32 {
     assert!(shift < 32);
@@ -38,22 +44,19 @@ fn sigmoid_0(x: u32) -> u32 {
 fn sigmoid_1(x: u32) -> u32 {
     rotate_right(x, 17) ^ rotate_right(x, 19) ^ (x >> 10)
 }
-fn u64_to_u32(a: u64) -> u32 {
-    (a & ((1u64 << 31) - 1)) as u32
-}
-// Compute the 64 32-bit blocks of the input, starting at the given byte index
-fn get_blocks(input: &Vec<u8>, start_ind: usize) -> [u32; 64] {
+// Compute the 64 32-bit blocks of a 64 byte chunk of input.
+// "All addition is modulo 2^32", and the words are read big endian
+fn get_blocks(chunk: &[u8; 64]) -> [u32; 64] {
     let mut blocks: [u32; 64] = [0; 64];
-    for i in 0usize..16usize {
-        let start: usize = start_ind + i * 4;
-        blocks[i] = u32::from_le_bytes([input[start], input[start + 1], input[start + 2], input[start + 3]]);
+    for (block, word) in blocks.iter_mut().zip(chunk.chunks_exact(4)) {
+        *block = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
     }
     // Now get the remaining 48
     for i in 16usize..64usize {
-        println!("sig0({}) = {}, sig1({}) = {}", blocks[i - 15], sigmoid_0(blocks[i - 15]), blocks[i - 2], sigmoid_1(blocks[i - 2]));
-        blocks[i] =
-            u64_to_u32(u64::from(sigmoid_1(blocks[i - 2])) + u64::from(blocks[i - 7]) +
-                u64::from(sigmoid_0(blocks[i - 15])) + u64::from(blocks[i - 16]))
+        blocks[i] = sigmoid_1(blocks[i - 2])
+            .wrapping_add(blocks[i - 7])
+            .wrapping_add(sigmoid_0(blocks[i - 15]))
+            .wrapping_add(blocks[i - 16]);
     }
     blocks
 }
@@ -69,65 +72,100 @@ fn get_new_hashes(old_hashes: &mut [u32; 8], blocks: [u32; 64]) {
     let mut h = old_hashes[7];
 
     for i in 0usize..64usize {
-        let t1: u64 = u64::from(h) + u64::from(sigma_1(e)) + u64::from(ch(e, f, g)) + u64::from(K[i]) + u64::from(blocks[i]);
-        let t2: u64 = u64::from(sigma_0(a)) + u64::from(maj(a, b, c));
+        let t1: u32 = h.wrapping_add(sigma_1(e)).wrapping_add(ch(e, f, g)).wrapping_add(K[i]).wrapping_add(blocks[i]);
+        let t2: u32 = sigma_0(a).wrapping_add(maj(a, b, c));
         h = g;
         g = f;
         f = e;
-        e = u64_to_u32(u64::from(d) + t1);
+        e = d.wrapping_add(t1);
         d = c;
         c = b;
         b = a;
-        a = u64_to_u32(t1 + t2);
+        a = t1.wrapping_add(t2);
     }
-    old_hashes[0] = u64_to_u32(u64::from(old_hashes[0]) + u64::from(a));
-    old_hashes[1] = u64_to_u32(u64::from(old_hashes[1]) + u64::from(b));
-    old_hashes[2] = u64_to_u32(u64::from(old_hashes[2]) + u64::from(c));
-    old_hashes[3] = u64_to_u32(u64::from(old_hashes[3]) + u64::from(d));
-    old_hashes[4] = u64_to_u32(u64::from(old_hashes[4]) + u64::from(e));
-    old_hashes[5] = u64_to_u32(u64::from(old_hashes[5]) + u64::from(f));
-    old_hashes[6] = u64_to_u32(u64::from(old_hashes[6]) + u64::from(g));
-    old_hashes[7] = u64_to_u32(u64::from(old_hashes[7]) + u64::from(h));
+    old_hashes[0] = old_hashes[0].wrapping_add(a);
+    old_hashes[1] = old_hashes[1].wrapping_add(b);
+    old_hashes[2] = old_hashes[2].wrapping_add(c);
+    old_hashes[3] = old_hashes[3].wrapping_add(d);
+    old_hashes[4] = old_hashes[4].wrapping_add(e);
+    old_hashes[5] = old_hashes[5].wrapping_add(f);
+    old_hashes[6] = old_hashes[6].wrapping_add(g);
+    old_hashes[7] = old_hashes[7].wrapping_add(h);
 }
 #[inline]
 fn u32s_to_u64(high: u32, low: u32) -> u64 {
     (u64::from(high) << 32) | u64::from(low)
 }
-// Hash the input.
-fn hash_input(input: &mut Vec<u8>) -> [u64; 4] {
-    let initial_length: u64 = input.len() as u64;
-    // First, add a one
-    // Then, add zeroes until the input length = 448 (mod 512)
-    let last_block_size: usize = ((input.len() << 3)) & 511;
-    let bits: usize = 448 + if last_block_size > 448 { 512 } else { 0 } - last_block_size;
-    println!("{:#?} needs {} bits", input, bits);
-    input.push(0b10000000);
-    // We can assume that the input had a number of bits divisble by 8,
-    // so don't worry about individual byte padding
-    for _ in 0..((bits - 8) >> 3) {
-        input.push(0);
-    }
-    for byte in initial_length.to_le_bytes() {
-        input.push(byte);
-    }
-    println!("{:?} {:?}", input.len(), input);
-
-    // Initialize 8 hash values to "the first 32 bits of the fractional part of the square roots of the
-    // first 8 prime numbers"
-    let mut hashes: [u32; 8] = [
-        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0x3c6ef372,
-        0x3c6ef372, 0x3c6ef372, 0x3c6ef372, 0x3c6ef372
-    ];
-    let mut byte_ind = 0;
-    while byte_ind < input.len() {
-        let blocks: [u32; 64] = get_blocks(input, byte_ind);
-    println!("Blocks: {:?}", blocks);
-        get_new_hashes(&mut hashes, blocks);
-        byte_ind = byte_ind + 512;
-        println!("{:?}", hashes);
-    }
-
-    println!("{:b}", hashes[0]);
+
+/// Incremental SHA-256, for when the input comes in pieces (like HMAC's key pad and message)
+#[derive(Clone)]
+pub struct Sha256 {
+    hashes: [u32; 8],
+    // Input that hasn't filled a 64 byte chunk yet
+    pending: [u8; 64],
+    pending_length: usize,
+    total_length: u64
+}
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Sha256 {
+    /// A hasher that hasn't seen any input yet
+    pub fn new() -> Self {
+        Self { hashes: INITIAL_HASHES, pending: [0; 64], pending_length: 0, total_length: 0 }
+    }
+    /// Hash some more input
+    pub fn update(&mut self, mut input: &[u8]) {
+        self.total_length += input.len() as u64;
+
+        while !input.is_empty() {
+            let take: usize = (64 - self.pending_length).min(input.len());
+            self.pending[self.pending_length..(self.pending_length + take)].copy_from_slice(&input[..take]);
+            self.pending_length += take;
+            input = &input[take..];
+
+            if self.pending_length == 64 {
+                get_new_hashes(&mut self.hashes, get_blocks(&self.pending));
+                self.pending_length = 0;
+            }
+        }
+    }
+    /// Pad the input and return the 32 byte digest
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_length: u64 = self.total_length << 3;
+        // First, add a one
+        // Then, add zeroes until the input length = 448 (mod 512), and finally the
+        // length of the input in bits as a big endian 64-bit number
+        self.update(&[0b10000000]);
+        while self.pending_length != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_length.to_be_bytes());
+
+        let mut digest: [u8; 32] = [0; 32];
+        for (chunk, hash) in digest.chunks_exact_mut(4).zip(self.hashes.iter()) {
+            chunk.copy_from_slice(&hash.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// SHA-256 of some bytes, all at once
+pub fn sha256_bytes(input: &[u8]) -> [u8; 32] {
+    let mut hasher: Sha256 = Sha256::new();
+    hasher.update(input);
+    hasher.finalize()
+}
+
+/// SHA-256 of a string, as four big endian u64s
+pub fn sha256(input: &str) -> [u64; 4] {
+    let digest: [u8; 32] = sha256_bytes(input.as_bytes());
+    let mut hashes: [u32; 8] = [0; 8];
+    for (hash, chunk) in hashes.iter_mut().zip(digest.chunks_exact(4)) {
+        *hash = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
     [
         u32s_to_u64(hashes[0], hashes[1]),
         u32s_to_u64(hashes[2], hashes[3]),
@@ -136,8 +174,132 @@ fn hash_input(input: &mut Vec<u8>) -> [u64; 4] {
     ]
 }
 
-pub fn sha256(input: &str) -> [u64; 4] {
-    assert_eq!(rotate_right(0xffeeddcc, 8), 0xccffeedd);
-    // assert_eq!(ch(0b1010001_00001110_01010010_01111111, 0b10011011_00000101_01101000_10001100, 0b00011111_10000011_11011001_10101011), 0b00011111_10000101_11001001_10001100);
-    hash_input(&mut input.as_bytes().to_vec())
-}
\ No newline at end of file
+/// HMAC-SHA256 (RFC 2104), split up so PBKDF2 can reuse the keyed inner and outer states
+/// instead of hashing the padded key again on every iteration
+#[derive(Clone)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256
+}
+impl HmacSha256 {
+    /// Start a MAC with this key
+    pub fn new(key: &[u8]) -> Self {
+        // Keys longer than a block get hashed down first
+        let mut block: [u8; 64] = [0; 64];
+        if key.len() > 64 {
+            block[..32].copy_from_slice(&sha256_bytes(key));
+        }
+        else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner: Sha256 = Sha256::new();
+        let mut outer: Sha256 = Sha256::new();
+        inner.update(&block.map(|byte| byte ^ 0x36));
+        outer.update(&block.map(|byte| byte ^ 0x5c));
+        Self { inner, outer }
+    }
+    /// MAC some more of the message
+    pub fn update(&mut self, input: &[u8]) {
+        self.inner.update(input);
+    }
+    /// The 32 byte tag for everything passed to update
+    pub fn finalize(self) -> [u8; 32] {
+        let mut outer: Sha256 = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+}
+/// HMAC-SHA256 of a whole message
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut hmac: HmacSha256 = HmacSha256::new(key);
+    hmac.update(message);
+    hmac.finalize()
+}
+
+/// PBKDF2 with HMAC-SHA256 (RFC 8018), filling the whole output buffer
+pub fn pbkdf2_sha256(passphrase: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    let keyed: HmacSha256 = HmacSha256::new(passphrase);
+
+    for (block_index, chunk) in output.chunks_mut(32).enumerate() {
+        // U_1 = HMAC(P, S || INT(i)), U_n = HMAC(P, U_n-1), and the block is all the U's XORed
+        let mut hmac: HmacSha256 = keyed.clone();
+        hmac.update(salt);
+        hmac.update(&(block_index as u32 + 1).to_be_bytes());
+        let mut last: [u8; 32] = hmac.finalize();
+        let mut block: [u8; 32] = last;
+
+        for _ in 1..iterations {
+            let mut hmac: HmacSha256 = keyed.clone();
+            hmac.update(&last);
+            last = hmac.finalize();
+            for (byte, new_byte) in block.iter_mut().zip(last.iter()) {
+                *byte ^= *new_byte;
+            }
+        }
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(|byte| byte.is_ascii_hexdigit()).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    // FIPS 180-4 examples (and the NIST example values for the empty and million 'a' messages)
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(sha256_bytes(b"").to_vec(), hex("e3b0c442 98fc1c14 9afbf4c8 996fb924 27ae41e4 649b934c a495991b 7852b855"));
+        assert_eq!(sha256_bytes(b"abc").to_vec(), hex("ba7816bf 8f01cfea 414140de 5dae2223 b00361a3 96177a9c b410ff61 f20015ad"));
+        assert_eq!(
+            sha256_bytes(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_vec(),
+            hex("248d6a61 d20638b8 e5c02693 0c3e6039 a33ce459 64ff2167 f6ecedd4 19db06c1")
+        );
+        assert_eq!(sha256_bytes(&[b'a'; 1_000_000]).to_vec(), hex("cdc76e5c 9914fb92 81a1c7e2 84d73e67 f1809a48 a497200e 046d39cc c7112cd0"));
+        assert_eq!(sha256("abc"), [0xba7816bf8f01cfea, 0x414140de5dae2223, 0xb00361a396177a9c, 0xb410ff61f20015ad]);
+    }
+
+    // Feeding the input in pieces that straddle the 64 byte chunks gives the same digest
+    #[test]
+    fn sha256_incremental_matches_one_shot() {
+        let input: Vec<u8> = (0..300u32).map(|byte| byte as u8).collect();
+        for split in [0, 1, 55, 56, 63, 64, 65, 128, 299] {
+            let mut hasher: Sha256 = Sha256::new();
+            hasher.update(&input[..split]);
+            hasher.update(&input[split..]);
+            assert_eq!(hasher.finalize(), sha256_bytes(&input));
+        }
+    }
+
+    // RFC 4231 test case 2
+    #[test]
+    fn hmac_sha256_known_answer() {
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?").to_vec(),
+            hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+    }
+
+    // RFC 7914 section 11
+    #[test]
+    fn pbkdf2_sha256_known_answers() {
+        let mut output: [u8; 64] = [0; 64];
+        pbkdf2_sha256(b"passwd", b"salt", 1, &mut output);
+        assert_eq!(output.to_vec(), hex("
+            55 ac 04 6e 56 e3 08 9f ec 16 91 c2 25 44 b6 05
+            f9 41 85 21 6d de 04 65 e6 8b 9d 57 c2 0d ac bc
+            49 ca 9c cc f1 79 b6 45 99 16 64 b3 9d 77 ef 31
+            7c 71 b8 45 b1 e3 0b d5 09 11 20 41 d3 a1 97 83"));
+
+        pbkdf2_sha256(b"Password", b"NaCl", 80000, &mut output);
+        assert_eq!(output.to_vec(), hex("
+            4d dc d8 f6 0b 98 be 21 83 0c ee 5e f2 27 01 f9
+            64 1a 44 18 d0 4c 04 14 ae ff 08 87 6b 34 ab 56
+            a1 d4 25 a1 22 58 33 54 9a db 84 1b 51 c9 b3 17
+            6a 27 2b de bb a1 d0 78 47 8f 62 b3 97 f3 3c 8d"));
+    }
+}
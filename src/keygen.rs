@@ -1,18 +1,31 @@
-use rand::prelude::*;
+use core::ops::Range;
+use rand::{ CryptoRng, Rng };
 #[cfg(feature = "std")]
-use std::fmt;
+use rand::rngs::ThreadRng;
 #[cfg(feature = "std")]
-use std::fmt::{ Debug, Display };
+use core::fmt;
+#[cfg(feature = "std")]
+use core::fmt::{ Debug, Display };
 
+use crate::modular::{ ModularContext, MontgomeryContext };
+use crate::multiply;
 use crate::primes;
+use crate::zeroize::{ Zeroize, Zeroizing };
 
-// The key is signed because we need intermediate negative values
-// during multiplicative modular inverse calculations
+/// The key is signed because we need intermediate negative values
+/// during multiplicative modular inverse calculations
 pub type Key = bnum::types::I4096;
+/// Unsigned and twice as wide as a Key, to hold the full product of two of them
+pub type WideKey = bnum::BUint<{ 2 * (Key::BITS / u64::BITS) as usize }>;
 
-// fn serialize_key(key: &Key) -> [u8; Key::BYTES_USIZE] {
-//     *key.to_le_bytes()
-// }
+/// Big-endian bytes of a key, since bnum only gives us the little-endian limbs
+pub fn key_to_be_bytes(key: &Key) -> [u8; Key::BYTES as usize] {
+    let mut bytes: [u8; Key::BYTES as usize] = [0; Key::BYTES as usize];
+    for (chunk, digit) in bytes.chunks_exact_mut(8).zip(key.to_bits().digits().iter().rev()) {
+        chunk.copy_from_slice(&digit.to_be_bytes());
+    }
+    bytes
+}
 
 /*
     Tested a GCD function like this:
@@ -27,215 +40,1078 @@ fn gcd(mut a: u64, mut b: u64) -> u64 {
     }
 }
 
-    That is actually tested to be slower than the following implementation:
+    That is actually tested to be slower than alternating a % b and b % a, and both are slower than
+    Stein's binary GCD below. A 4096-bit % is a full long division, while this only shifts and subtracts
 */
-fn gcd(a: Key, b: Key) -> Key {
-    if a == Key::ZERO || b == Key::ZERO { return Key::ZERO; }
+/// The GCD of |a| and |b|, with Stein's binary GCD
+pub fn gcd(a: Key, b: Key) -> Key {
+    let mut copy_a: Key = a.abs();
+    let mut copy_b: Key = b.abs();
+    if copy_a == Key::ZERO { return copy_b; }
+    if copy_b == Key::ZERO { return copy_a; }
 
-    let mut copy_a: Key = a;
-    let mut copy_b: Key = b;
-    while copy_a != copy_b {
-        if copy_a == copy_b { return copy_a; }
-        if copy_a > copy_b {
-            copy_a = copy_a % copy_b;
-            if copy_a == Key::ZERO { return copy_b; };
-        }
-        else {
-            copy_b = copy_b % copy_a;
-            if copy_b == Key::ZERO { return copy_a; }
-        }
+    // gcd(2^i a, 2^j b) = 2^min(i, j) gcd(a, b), so pull out the common twos and put them back at the end
+    let shift: u32 = (copy_a | copy_b).trailing_zeros();
+    copy_a >>= copy_a.trailing_zeros();
+    loop {
+        // a is odd here. If b is odd too, then gcd(a, b) = gcd(a, b - a), and b - a is even
+        copy_b >>= copy_b.trailing_zeros();
+        if copy_a > copy_b { (copy_a, copy_b) = (copy_b, copy_a); }
+        copy_b -= copy_a;
+        if copy_b == Key::ZERO { return copy_a << shift; }
     }
-    return copy_a;
 }
 fn are_coprime(a: Key, b: Key) -> bool {
     // Are they both even? If so, they're not coprime
     if ((a | b) & Key::ONE) == Key::ZERO { return false; }
 
-    return gcd(a, b) == Key::ONE;
-}
-// Find modular inverse i such that ai = 1 (mod b),
-// ASSUMING n and b are coprime
-pub fn get_modular_inverse(mut a: Key, mut b: Key) -> Key {
-    // Using Extended Euclidean algorithm
-
-    // Express r0 = x0 * a + m0
-    // let mut r0: u64 = m;
-    // let mut a0: u64 = a;
-    // while a0 > 1 {
-    //     // r0 = x0 * a0 + m0
-    //     let x0: u64 = r0 / a0;
-    //     let m0: u64 = r0 - x0 * a0;
-    //     println!("{}  = {} * {} + {}", r0, x0, a0, m0);
-    //     r0 = a0;
-    //     a0 = m0;
-    // }
-    // 1
-
-    let original_b: Key = b;
-
-    let (mut x, mut u) = (Key::ZERO, Key::ONE);
-    // Recursively find such that x0 = m0 * a1 + m1
-    while a != Key::ZERO {
-        let q = b / a;
-        let r = b - a * q;
-        println!("{}  = {} * {} + {}", b, a, q, r);
-        let m = x - u * q;
-        (b, a, x, u) = (a, r, u, m);
+    gcd(a, b) == Key::ONE
+}
+
+/// The number has no inverse, because it shares a factor with the modulus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotInvertible;
+
+/// Binary extended GCD (Handbook of Applied Cryptography 14.61). Returns (g, x, y) with ax + by = g = gcd(a, b),
+/// for positive a and b. Like the binary GCD, it only shifts, adds and subtracts
+pub fn binary_extended_gcd(a: Key, b: Key) -> (Key, Key, Key) {
+    let mut shift: u32 = 0;
+    let (mut x, mut y) = (a, b);
+    while ((x | y) & Key::ONE) == Key::ZERO {
+        x >>= 1u32;
+        y >>= 1u32;
+        shift += 1;
+    }
+
+    // Keep u = xA + yB and v = xC + yD the whole way through
+    let (mut u, mut v) = (x, y);
+    let (mut coefficient_a, mut coefficient_b, mut coefficient_c, mut coefficient_d) = (Key::ONE, Key::ZERO, Key::ZERO, Key::ONE);
+    loop {
+        while (u & Key::ONE) == Key::ZERO {
+            u >>= 1u32;
+            // Halve A and B, first adding (y, -x) to make them even if they aren't
+            if ((coefficient_a | coefficient_b) & Key::ONE) != Key::ZERO {
+                coefficient_a += y;
+                coefficient_b -= x;
+            }
+            coefficient_a >>= 1u32;
+            coefficient_b >>= 1u32;
+        }
+        while (v & Key::ONE) == Key::ZERO {
+            v >>= 1u32;
+            if ((coefficient_c | coefficient_d) & Key::ONE) != Key::ZERO {
+                coefficient_c += y;
+                coefficient_d -= x;
+            }
+            coefficient_c >>= 1u32;
+            coefficient_d >>= 1u32;
+        }
+
+        if u >= v {
+            u -= v;
+            coefficient_a -= coefficient_c;
+            coefficient_b -= coefficient_d;
+        }
+        else {
+            v -= u;
+            coefficient_c -= coefficient_a;
+            coefficient_d -= coefficient_b;
+        }
+        if u == Key::ZERO { return (v << shift, coefficient_c, coefficient_d); }
     }
-    // If X is negative, add the original value of b to it --
-    // we can do that since X is the modular inverse mod b
-    if x < Key::ZERO { x = x + original_b; }
-    return x;
 }
 
-// Compute s^e mod m
-pub fn bigmod(s: Key, mut e: Key, m: Key) -> Key {
-    // We're essentially going to multiple s^n for every (1 << b), accounting
-    // for every set bit in e
-    let mut final_mod: Key = Key::ONE;
-    // The mod of s^(2^n)
-    let mut last_mod: Key = s;
+/// Find the modular inverse i such that ai = 1 (mod m), using the binary extended GCD.
+/// The running time depends on the inputs, so this is for public values only
+pub fn get_modular_inverse(a: Key, m: Key) -> Result<Key, NotInvertible> {
+    if m <= Key::ONE { return Err(NotInvertible); }
+    let reduced: Key = a.rem_euclid(m);
+    if reduced == Key::ZERO { return Err(NotInvertible); }
 
-    while e > Key::ZERO {
-        if (e & Key::ONE) == Key::ONE { final_mod = (final_mod * last_mod) % m; };
-        last_mod = (last_mod * last_mod) % m;
-        e = e >> Key::ONE;
+    let (divisor, inverse, _) = binary_extended_gcd(reduced, m);
+    if divisor != Key::ONE { return Err(NotInvertible); }
+    Ok(inverse.rem_euclid(m))
+}
+
+// All ones if the bit is set, all zeroes if not
+#[inline]
+fn mask_from_bit(bit: Key) -> Key {
+    Key::ZERO - (bit & Key::ONE)
+}
+// Pick a where the mask is all ones, b where it's all zeroes, without branching
+#[inline]
+fn select(mask: Key, a: Key, b: Key) -> Key {
+    (a & mask) | (b & !mask)
+}
+// x - m if that's not negative, otherwise x. The sign bit of x - m makes the mask
+#[inline]
+fn conditional_subtract(x: Key, m: Key) -> Key {
+    let difference: Key = x - m;
+    select(difference >> (Key::BITS - 1), x, difference)
+}
+// x / 2 mod m, for odd m and 0 <= x < m: x / 2 if x is even, or (x + m) / 2 if it's odd
+#[inline]
+fn half_mod_odd(x: Key, m: Key) -> Key {
+    (x + (m & mask_from_bit(x))) >> 1u32
+}
+
+// Constant-time modular inverse for an odd modulus, with Bernstein and Yang's divsteps
+// ("Fast constant-time gcd computation and modular inversion", 2019). It always runs the
+// same number of steps for a given modulus size, and every choice is made with masks instead
+// of branches, so the timing doesn't depend on the (secret) number being inverted
+fn modular_inverse_odd_ct(a: Key, m: Key) -> Result<Key, NotInvertible> {
+    // Theorem 11.2: this many steps always gets g to zero for d-bit inputs
+    let bits: i64 = m.bits() as i64;
+    let iterations: i64 = if bits < 46 { (49 * bits + 80) / 17 } else { (49 * bits + 57) / 17 };
+
+    // Keep f = da and g = ea (mod m) the whole way through
+    let mut delta: i64 = 1;
+    let (mut f, mut g) = (m, a.rem_euclid(m));
+    let (mut d, mut e) = (Key::ZERO, Key::ONE);
+    for _ in 0..iterations {
+        // If delta > 0 and g is odd, then (delta, f, g) = (-delta, g, -f), and the same for d and e.
+        // f is always odd, so g stays odd if they swap
+        let g_odd_bit: i64 = g.bit(0) as i64;
+        let swap_bit: i64 = g_odd_bit & (((-delta) >> 63) & 1);
+        let swap: Key = mask_from_bit(Key::from(swap_bit));
+        let negated_f: Key = Key::ZERO - f;
+        let negated_d: Key = conditional_subtract(m - d, m);
+        (f, g) = (select(swap, g, f), select(swap, negated_f, g));
+        (d, e) = (select(swap, e, d), select(swap, negated_d, e));
+        delta = (delta ^ -swap_bit) + swap_bit;
+
+        // Then g = (g + f) / 2 if g is odd, or g / 2 if it's even, and the same for e (mod m)
+        let g_odd: Key = mask_from_bit(Key::from(g_odd_bit));
+        g = (g + (f & g_odd)) >> 1u32;
+        e = half_mod_odd(conditional_subtract(e + (d & g_odd), m), m);
+        delta += 1;
     }
-    return final_mod;
+
+    // Now g is zero and f is +-gcd(a, m)
+    if f != Key::ONE && f != Key::NEG_ONE { return Err(NotInvertible); }
+    Ok(select(mask_from_bit(f >> (Key::BITS - 1)), conditional_subtract(m - d, m), d))
+}
+
+/// Constant-time modular inverse, for secret values like private exponents and CRT coefficients.
+/// Divsteps need an odd modulus. If the modulus is even (like lambda(n)) but a is odd, use
+/// a^-1 mod m = (1 + m(a - (m^-1 mod a))) / a, which only needs an inverse mod a. That last
+/// division takes time depending on a, so a should be public then, as the RSA public exponent is
+pub fn get_modular_inverse_ct(a: Key, m: Key) -> Result<Key, NotInvertible> {
+    if m <= Key::ONE { return Err(NotInvertible); }
+    if (m & Key::ONE) == Key::ONE { return modular_inverse_odd_ct(a, m); }
+
+    let reduced: Key = a.rem_euclid(m);
+    if (reduced & Key::ONE) == Key::ZERO { return Err(NotInvertible); }
+    if reduced == Key::ONE { return Ok(Key::ONE); }
+    let inverse_of_m: Key = modular_inverse_odd_ct(m, reduced)?;
+    Ok(narrow((widening_mul(m, reduced - inverse_of_m) + WideKey::ONE) / widen(reduced)))
+}
+
+// Scratch space for multiplying two Keys, which is small enough to keep on the stack
+const KEY_SCRATCH_LIMBS: usize = multiply::scratch_limbs((Key::BITS / u64::BITS) as usize);
+
+/// The full product of two non-negative Keys, multiplying only the limbs that are in use
+pub fn widening_mul(a: Key, b: Key) -> WideKey {
+    debug_assert!(!a.is_negative() && !b.is_negative());
+    let (a, b) = (a.to_bits(), b.to_bits());
+    let a_limbs: usize = multiply::significant_limbs(a.digits());
+    let b_limbs: usize = multiply::significant_limbs(b.digits());
+
+    let mut product: WideKey = WideKey::ZERO;
+    let mut scratch: [u64; KEY_SCRATCH_LIMBS] = [0; KEY_SCRATCH_LIMBS];
+    multiply::mul_with_scratch(&a.digits()[..a_limbs], &b.digits()[..b_limbs],
+        &mut product.digits_mut()[..(a_limbs + b_limbs)], &mut scratch);
+    product
+}
+/// The full square of a non-negative Key, which takes about half the work of widening_mul
+pub fn widening_square(a: Key) -> WideKey {
+    debug_assert!(!a.is_negative());
+    let a = a.to_bits();
+    let limbs: usize = multiply::significant_limbs(a.digits());
+
+    let mut product: WideKey = WideKey::ZERO;
+    let mut scratch: [u64; KEY_SCRATCH_LIMBS] = [0; KEY_SCRATCH_LIMBS];
+    multiply::square_with_scratch(&a.digits()[..limbs], &mut product.digits_mut()[..(2 * limbs)], &mut scratch);
+    product
+}
+// A non-negative Key as a WideKey
+pub(crate) fn widen(key: Key) -> WideKey {
+    let mut wide: WideKey = WideKey::ZERO;
+    wide.digits_mut()[..(Key::BITS / u64::BITS) as usize].copy_from_slice(key.to_bits().digits());
+    wide
+}
+// The low half of a WideKey, for results we know fit
+pub(crate) fn narrow(wide: WideKey) -> Key {
+    let mut bits: bnum::types::U4096 = bnum::types::U4096::ZERO;
+    bits.digits_mut().copy_from_slice(&wide.digits()[..(Key::BITS / u64::BITS) as usize]);
+    Key::from_bits(bits)
+}
+/// a * b mod m and a^2 mod m, for 0 <= a, b and 0 < m. The product doesn't have to fit in a Key.
+/// These divide by m every time, so anything reducing by the same modulus more than once should
+/// use a context from modular.rs instead
+/// ab mod m, for non-negative a and b and positive m
+pub fn mul_mod(a: Key, b: Key, m: Key) -> Key {
+    narrow(widening_mul(a, b) % widen(m))
+}
+/// a^2 mod m, for non-negative a and positive m
+pub fn square_mod(a: Key, m: Key) -> Key {
+    narrow(widening_square(a) % widen(m))
+}
+
+/// Compute s^e mod m, checking the arguments first. The modulus has to be positive and small enough
+/// that the sums of two residues fit in a Key, and the exponent can't be negative
+pub fn bigmod(s: Key, e: Key, m: Key) -> Result<Key, Error> {
+    if m <= Key::ZERO || m.bits() > MAX_MODULUS_BITS { return Err(Error::InvalidModulus); }
+    if e < Key::ZERO { return Err(Error::NegativeExponent); }
+    Ok(bigmod_unchecked(s.rem_euclid(m), e, m))
+}
+// Compute s^e mod m, for a modulus we already know is valid and 0 <= s < m. Squaring and
+// multiplying for each bit of e is up to two products per bit, which decides the reduction
+fn bigmod_unchecked(s: Key, e: Key, m: Key) -> Key {
+    ModularContext::new_unchecked(m, 2 * e.bits() as usize).pow(s, e)
 }
 
 // Test one case of the Miller-Rabin for a potential prime p and a base A, given (p - 1)'s mantissa.
 // e.g. the number M that satisfies p - 1 = 2^N * M
-// Assumes that p is odd
+// p is odd, since it's the modulus of the Montgomery context, and every power stays in Montgomery form
 // If any of the following is not true:
 //   A^(p - 1) = 1 (mod p)
 //   A^((p - 1)/2) = 1 (mod p) OR A^((p - 1)/2) = -1 (mod p)
 // Run the last test for {M, M * 2, M * 2^2, M * 2^3, ... prime}
 // It's not a prime. Return false if number is not a prime, true if there's a 3/4 chance it is
-fn number_passes_miller_rabin(mut mantissa: Key, prime: Key, base: Key) -> bool {
-    // Make sure it's odd
-    assert!((prime & Key::ONE) == Key::ONE);
+fn number_passes_miller_rabin(mut mantissa: Key, context: &MontgomeryContext, base: Key) -> bool {
+    let prime: Key = context.modulus();
+    let (one, minus_one) = (context.one(), context.minus_one());
 
-    let mut power: Key = bigmod(base, mantissa, prime);
-    if power == Key::ONE || power == (prime - Key::ONE) { return true; }
+    let mut power: Key = context.pow_montgomery(context.to_montgomery(base), mantissa);
+    if power == one || power == minus_one { return true; }
     
     while mantissa < (prime - Key::ONE) {
-        power = (power * power) % prime;
-        mantissa = mantissa << Key::ONE;
-        if power == Key::ONE || power == (prime - Key::ONE) { return true; }
+        power = context.square(power);
+        mantissa <<= Key::ONE;
+        if power == one || power == minus_one { return true; }
     }
 
-    return false;
+    false
 }
 
-pub struct NumberHandler {
-    key_byte_size: usize,
-    rng: ThreadRng
+/// Floor of the square root of a non-negative key, using Newton's method
+pub fn isqrt(num: Key) -> Key {
+    if num < Key::TWO { return num; }
+
+    // Start above the root so the iteration only ever moves down
+    let mut root: Key = Key::ONE << ((num.bits() + 1) >> 1);
+    loop {
+        let next: Key = (root + num / root) >> Key::ONE;
+        if next >= root { return root; }
+        root = next;
+    }
 }
-impl NumberHandler {
-    pub fn new(key_byte_size: usize) -> Self {
-        Self { key_byte_size, rng: rand::rng() }
+fn is_perfect_square(num: Key) -> bool {
+    let root: Key = isqrt(num);
+    root * root == num
+}
+
+// Jacobi symbol (a/n) for an odd positive n. Returns -1, 0 or 1
+fn jacobi_symbol(a: Key, n: Key) -> i8 {
+    let mut a: Key = a.rem_euclid(n);
+    let mut n: Key = n;
+    let mut result: i8 = 1;
+
+    while a != Key::ZERO {
+        // Pull out factors of two: (2/n) = -1 exactly when n = 3 or 5 (mod 8)
+        while (a & Key::ONE) == Key::ZERO {
+            a >>= Key::ONE;
+            let n_mod_8: Key = n & Key::SEVEN;
+            if n_mod_8 == Key::THREE || n_mod_8 == Key::FIVE { result = -result; }
+        }
+        // Quadratic reciprocity flips the sign when both are 3 (mod 4)
+        (a, n) = (n, a);
+        if (a & Key::THREE) == Key::THREE && (n & Key::THREE) == Key::THREE { result = -result; }
+        a %= n;
     }
-    pub fn get_rng(&mut self) -> &mut ThreadRng {
-        return &mut self.rng;
+
+    if n == Key::ONE { result } else { 0 }
+}
+
+// Halve x mod n, for an odd n
+#[inline]
+fn half_mod(x: Key, n: Key) -> Key {
+    if (x & Key::ONE) == Key::ONE { (x + n) >> Key::ONE } else { x >> Key::ONE }
+}
+
+// Strong Lucas probable prime test with Selfridge's parameters: D is the first of
+// 5, -7, 9, -11, ... with (D/n) = -1, P = 1 and Q = (1 - D) / 4.
+// Assumes n is odd, greater than 3 and not a perfect square. Adding, halving and comparing with
+// zero all work the same on numbers in Montgomery form, so U, V and Q^k stay in it throughout
+fn passes_strong_lucas(context: &MontgomeryContext) -> bool {
+    let n: Key = context.modulus();
+    let mut d: Key = Key::FIVE;
+    loop {
+        match jacobi_symbol(d, n) {
+            -1 => break,
+            // n shares a factor with D. It's only prime if it IS that factor
+            0 => return d.abs() == n,
+            _ => {}
+        }
+        d = if d.is_negative() { Key::TWO - d } else { -(d + Key::TWO) };
     }
-    
-    fn get_random_u8(&mut self) -> u8 {
-        self.get_rng().random::<u8>()
+    let q: Key = context.to_montgomery(((Key::ONE - d) >> Key::TWO).rem_euclid(n));
+    let d: Key = context.to_montgomery(d.rem_euclid(n));
+
+    // n + 1 = 2^s * m, with m odd
+    let mut m: Key = n + Key::ONE;
+    let mut s: u32 = 0;
+    while (m & Key::ONE) == Key::ZERO {
+        m >>= Key::ONE;
+        s += 1;
     }
-    // Ensures that the number is at least 1 << bits
-    fn get_random_n_byte_key(&mut self, byte_count: usize) -> Key {
-        let mut bytes: [u8; Key::BYTES as usize] = [0; Key::BYTES as usize];
-        for ind in 0usize..byte_count {
-            let mut byte: u8 = self.get_random_u8();
-            if ind == byte_count - 1 { byte = byte | 0b10000000; }
-            bytes[ind] = byte;
+
+    // Walk down the bits of m, keeping U_k, V_k and Q^k (mod n). Starting at k = 1:
+    //   U_2k = U_k * V_k, V_2k = V_k^2 - 2Q^k, Q^2k = (Q^k)^2
+    //   U_k+1 = (U_k + V_k) / 2, V_k+1 = (D * U_k + V_k) / 2, Q^k+1 = Q * Q^k
+    let mut u: Key = context.one();
+    let mut v: Key = context.one();
+    let mut q_k: Key = q;
+    for bit in (0..(m.bits() - 1)).rev() {
+        u = context.mul(u, v);
+        v = (context.square(v) - Key::TWO * q_k).rem_euclid(n);
+        q_k = context.square(q_k);
+        if m.bit(bit) {
+            (u, v) = (half_mod((u + v) % n, n), half_mod((context.mul(d, u) + v) % n, n));
+            q_k = context.mul(q_k, q);
+        }
+    }
+
+    // Strong test: U_m = 0, or V_(m * 2^r) = 0 for some 0 <= r < s
+    if u == Key::ZERO || v == Key::ZERO { return true; }
+    for _r in 1..s {
+        v = (context.square(v) - Key::TWO * q_k).rem_euclid(n);
+        q_k = context.square(q_k);
+        if v == Key::ZERO { return true; }
+    }
+    false
+}
+
+/// Baillie-PSW primality test: a base-2 strong probable prime test followed by a strong
+/// Lucas test. No composite is known to pass both, and none exist below 2^64, so unlike
+/// Miller-Rabin with random bases there is no iteration count to guess
+pub fn passes_baillie_psw(num: Key) -> Result<bool, Error> {
+    if num.bits() > MAX_MODULUS_BITS { return Err(Error::InvalidModulus); }
+    Ok(baillie_psw(num))
+}
+fn baillie_psw(num: Key) -> bool {
+    if num < Key::FOUR { return num == Key::TWO || num == Key::THREE; }
+    if (num & Key::ONE) == Key::ZERO { return false; }
+
+    let mut mantissa: Key = num - Key::ONE;
+    while (mantissa & Key::ONE) == Key::ZERO {
+        mantissa >>= Key::ONE;
+    }
+    // Both tests take a few products per bit of num, which is plenty for Montgomery to pay off
+    let context: MontgomeryContext = MontgomeryContext::new_unchecked(num);
+    if !number_passes_miller_rabin(mantissa, &context, Key::TWO) { return false; }
+
+    // The Selfridge search for D never ends on a perfect square, so rule those out first
+    if is_perfect_square(num) { return false; }
+    passes_strong_lucas(&context)
+}
+
+/// Number of random-base Miller-Rabin rounds to run on top of Baillie-PSW for a prime of the
+/// given bit length. These follow the "M-R Tests Only" column of FIPS 186-5 Table B.1
+/// (error probability 2^-100 to 2^-144), not the smaller "M-R + Lucas" counts. That column
+/// assumes the Lucas test comes after the rounds, but here it comes first, inside Baillie-PSW.
+/// The stricter counts reach the table's error bound without taking any credit for the Lucas
+/// test, so the order doesn't matter. They're cheap too: the rounds only run on candidates that
+/// already passed Baillie-PSW, which are almost always prime, so they cost a few modexps per prime.
+/// The table starts at 512 bits because its counts rely on average-case error bounds for random
+/// candidates, and those need large numbers. Below that, 40 rounds get the worst-case bound of
+/// 4^-40 = 2^-80 for any odd number, which small numbers can easily afford
+pub fn miller_rabin_rounds(bits: u32) -> u8 {
+    match bits {
+        2048.. => 4,
+        1536.. => 4,
+        1024.. => 5,
+        512.. => 7,
+        _ => 40
+    }
+}
+
+/// Remainders of a Key by several word-sized divisors, in one pass over its limbs from the top
+/// down. Each step folds the next 64-bit limb into a u128 remainder, so this costs one machine
+/// division per limb per divisor instead of building a Key out of each divisor and doing a full
+/// bignum division. Remainders are non-negative, like rem_euclid. Divisors can't be zero
+pub fn rem_u64_batch(num: Key, divisors: &[u64], remainders: &mut [u64]) {
+    let magnitude = num.unsigned_abs();
+    let limbs: usize = magnitude.bits().div_ceil(u64::BITS) as usize;
+    remainders.fill(0);
+    for &limb in magnitude.digits()[..limbs].iter().rev() {
+        for (remainder, divisor) in remainders.iter_mut().zip(divisors.iter()) {
+            *remainder = ((((*remainder as u128) << u64::BITS) | limb as u128) % *divisor as u128) as u64;
         }
-        match Key::from_le_slice(&bytes) {
-            Some(key) => key,
-            None      => Key::ZERO
+    }
+    if num.is_negative() {
+        for (remainder, divisor) in remainders.iter_mut().zip(divisors.iter()) {
+            if *remainder != 0 { *remainder = divisor - *remainder; }
         }
     }
+}
+/// num mod divisor, for a non-negative num
+pub fn rem_u64(num: Key, divisor: u64) -> u64 {
+    let mut remainder: [u64; 1] = [0];
+    rem_u64_batch(num, &[divisor], &mut remainder);
+    remainder[0]
+}
+
+// The residues of a prime candidate modulo every prime in primes::FIRST_PRIMES.
+// Computing them takes one pass over the candidate's limbs for all of primes::PRIME_PRODUCTS,
+// and after that moving the candidate forward only needs each residue bumped, which is far
+// cheaper than dividing a 4096-bit Key by every small prime for every candidate
+struct SmallPrimeResidues {
+    residues: [u64; primes::FIRST_PRIMES.len()]
+}
+impl SmallPrimeResidues {
+    fn new(candidate: Key) -> Self {
+        let mut residues: [u64; primes::FIRST_PRIMES.len()] = [0; primes::FIRST_PRIMES.len()];
+        let mut product_residues: [u64; primes::PRIME_PRODUCT_COUNT] = [0; primes::PRIME_PRODUCT_COUNT];
+        rem_u64_batch(candidate, &primes::PRIME_PRODUCTS, &mut product_residues);
+
+        let mut ind: usize = 0;
+        for (product, remainder) in primes::PRIME_PRODUCTS.into_iter().zip(product_residues) {
+            // Each product is made of the next few primes in the table
+            let mut rest: u64 = product;
+            while rest > 1 {
+                residues[ind] = remainder % primes::FIRST_PRIMES[ind];
+                rest /= primes::FIRST_PRIMES[ind];
+                ind += 1;
+            }
+        }
+        Self { residues }
+    }
+    // Move the candidate forward by step
+    fn advance(&mut self, step: u64) {
+        for (residue, prime) in self.residues.iter_mut().zip(primes::FIRST_PRIMES.iter()) {
+            *residue = (*residue + step) % prime;
+        }
+    }
+    // Is the candidate divisible by one of the small primes? A small prime
+    // is of course divisible by itself, so that doesn't count
+    fn has_small_factor(&self, candidate: Key) -> bool {
+        for (residue, prime) in self.residues.iter().zip(primes::FIRST_PRIMES.iter()) {
+            if *residue == 0 && candidate != Key::from(*prime) { return true; }
+        }
+        false
+    }
+    // Combined sieve for a safe prime candidate p = 2q + 1. q is divisible by a small
+    // prime r exactly when p = 1 (mod r), so one set of residues rules out both
+    fn rules_out_safe_prime(&self, candidate: Key) -> bool {
+        let sophie_germain: Key = candidate >> Key::ONE;
+        for (residue, prime) in self.residues.iter().zip(primes::FIRST_PRIMES.iter()) {
+            if *residue == 0 && candidate != Key::from(*prime) { return true; }
+            if *residue == 1 && sophie_germain != Key::from(*prime) { return true; }
+        }
+        false
+    }
+}
+
+/// Where the candidates in a NumberHandler's prime searches went, as running totals. Every candidate
+/// is either rejected by trial division, rejected by the bignum tests, or found to be prime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrimeSearchStats {
+    /// Every number the searches looked at
+    pub candidates: u64,
+    /// A small prime divides it, which only costs a few u64 operations to find out
+    pub rejected_by_trial_division: u64,
+    /// Baillie-PSW or Miller-Rabin proved it composite
+    pub rejected_by_primality_test: u64,
+    /// Candidates that passed every test
+    pub primes_found: u64,
+    /// Miller-Rabin rounds run, counting the base 2 round in Baillie-PSW
+    pub miller_rabin_rounds: u64,
+    /// How many of those rounds were spent on numbers that turned out to be composite
+    pub rounds_on_composites: u64
+}
+
+/// A safe prime p = 2q + 1, where q is also prime (a Sophie Germain prime)
+#[derive(Clone, Copy)]
+pub struct SafePrime {
+    /// The safe prime p
+    pub prime: Key,
+    /// q = (p - 1) / 2
+    pub sophie_germain: Key
+}
+
+/// DSA/Diffie-Hellman style parameters: a prime p, a prime q dividing p - 1,
+/// and a generator g of the subgroup of order q mod p
+#[derive(Clone, Copy)]
+pub struct SubgroupPrimes {
+    /// The prime p
+    pub prime: Key,
+    /// q, which divides p - 1
+    pub subgroup_order: Key,
+    /// g, which has order q mod p
+    pub generator: Key
+}
+
+/// Why a keygen function couldn't do what it was asked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Zero bytes, or too big for bigmod
+    InvalidKeySize(usize),
+    /// Fewer than two bits, or too big for bigmod
+    InvalidBitSize(u32),
+    /// Subgroups have to be smaller than the key size
+    InvalidSubgroupSize(usize),
+    /// Not positive, or too big for bigmod
+    InvalidModulus,
+    /// bigmod doesn't do inverses, so the exponent can't be negative
+    NegativeExponent,
+    /// Asked for a random number from a range with nothing in it
+    EmptyRange,
+    /// Asked for a number coprime to zero
+    NoCoprimes,
+    /// The number has no inverse mod the modulus
+    NotInvertible,
+    /// A key failed validation
+    Invalid(ValidationError)
+}
+impl From<NotInvertible> for Error {
+    fn from(_: NotInvertible) -> Self {
+        Error::NotInvertible
+    }
+}
+impl From<ValidationError> for Error {
+    fn from(error: ValidationError) -> Self {
+        Error::Invalid(error)
+    }
+}
+#[cfg(feature = "std")]
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidKeySize(size) => write!(f, "a key size of {} bytes isn't supported", size),
+            Error::InvalidBitSize(bits) => write!(f, "a size of {} bits isn't supported", bits),
+            Error::InvalidSubgroupSize(size) => write!(f, "a subgroup of {} bytes doesn't fit in the key size", size),
+            Error::InvalidModulus => write!(f, "modulus is not positive or too large"),
+            Error::NegativeExponent => write!(f, "exponent is negative"),
+            Error::EmptyRange => write!(f, "range is empty"),
+            Error::NoCoprimes => write!(f, "no number is coprime to zero"),
+            Error::NotInvertible => write!(f, "number has no modular inverse"),
+            Error::Invalid(error) => write!(f, "key failed validation: {}", error)
+        }
+    }
+}
+
+// A key of this many bytes has to fit in a modulus bigmod can work with
+fn check_key_size(key_byte_size: usize) -> Result<(), Error> {
+    if key_byte_size == 0 || key_byte_size > (MAX_MODULUS_BITS >> 3) as usize {
+        return Err(Error::InvalidKeySize(key_byte_size));
+    }
+    Ok(())
+}
+/// The smallest RSA primes that can make a modulus bigger than 11519^2 (see RSAPublicKey::validate)
+pub const MIN_RSA_PRIME_BYTES: usize = 2;
+/// RSA primes also need their product to fit, and the modulus to be big enough to validate
+pub fn check_rsa_prime_size(key_byte_size: usize) -> Result<(), Error> {
+    if key_byte_size < MIN_RSA_PRIME_BYTES || (key_byte_size << 4) > MAX_MODULUS_BITS as usize {
+        return Err(Error::InvalidKeySize(key_byte_size));
+    }
+    Ok(())
+}
+
+/// Every random number comes from the RNG the handler is built with. With std that's the thread
+/// RNG by default, and without it the caller brings one, like a hardware RNG on an embedded board.
+/// It has to be a CryptoRng, since the primes it picks end up in private keys
+pub struct NumberHandler<R: CryptoRng> {
+    key_byte_size: usize,
+    rng: R,
+    stats: PrimeSearchStats
+}
+#[cfg(feature = "std")]
+impl NumberHandler<ThreadRng> {
+    /// A handler for keys of this many bytes, using the thread-local RNG
+    pub fn new(key_byte_size: usize) -> Result<Self, Error> {
+        Self::with_rng(key_byte_size, rand::rng())
+    }
+}
+impl<R: CryptoRng> NumberHandler<R> {
+    /// Keys have to fit in a modulus bigmod can work with
+    pub fn with_rng(key_byte_size: usize, rng: R) -> Result<Self, Error> {
+        check_key_size(key_byte_size)?;
+        Ok(Self { key_byte_size, rng, stats: PrimeSearchStats::default() })
+    }
+    /// The random number generator, for drawing randomness the handler doesn't provide itself
+    pub fn get_rng(&mut self) -> &mut R {
+        &mut self.rng
+    }
+    /// Totals for every prime search since the handler was made or the stats were last reset
+    pub fn search_stats(&self) -> PrimeSearchStats {
+        self.stats
+    }
+    /// Set the search stats back to zero
+    pub fn reset_search_stats(&mut self) {
+        self.stats = PrimeSearchStats::default();
+    }
+
+    // A uniformly random 0 <= N < 2^bits, for bits < Key::BITS. Only the bytes that can be set
+    // get filled, and the top one is masked, so this never shifts into the sign bit
+    fn get_random_bits(&mut self, bits: u32) -> Key {
+        debug_assert!(bits < Key::BITS);
+        let byte_count: usize = bits.div_ceil(8) as usize;
+        let mut bytes: [u8; Key::BYTES as usize] = [0; Key::BYTES as usize];
+        self.get_rng().fill(&mut bytes[..byte_count]);
+        if !bits.is_multiple_of(8) { bytes[byte_count - 1] &= (1u8 << (bits % 8)) - 1; }
+        Key::from_le_slice(&bytes).unwrap_or(Key::ZERO)
+    }
+    /// A random number of exactly this many bits, with the top two set. Two numbers like this
+    /// always multiply to exactly twice as many bits, which is what RSA primes need
+    pub fn get_random_n_bit_key(&mut self, bits: u32) -> Result<Key, Error> {
+        if !(2..=MAX_MODULUS_BITS).contains(&bits) { return Err(Error::InvalidBitSize(bits)); }
+        Ok(self.get_random_bits(bits - 2) | (Key::THREE << (bits - 2)))
+    }
     #[inline]
-    fn get_random_key(&mut self, ensure_odd: bool) -> Key {
-        self.get_random_n_byte_key(self.key_byte_size) | (if ensure_odd { Key::ONE } else { Key::ZERO }) 
+    fn get_random_key(&mut self) -> Result<Key, Error> {
+        self.get_random_n_bit_key(self.key_bits())
+    }
+    /// A uniformly random start <= N < end. Taking a bigger random number mod the range's length
+    /// would favour the low end, so draw just enough bits and try again whenever we land past
+    /// the end. That happens less than half the time, since the length needs all of those bits
+    pub fn get_random_in_range(&mut self, range: Range<Key>) -> Result<Key, Error> {
+        if range.end <= range.start { return Err(Error::EmptyRange); }
+        // The length takes every bit of a Key if the ends are too far apart
+        let Some(length) = range.end.checked_sub(range.start) else { return Err(Error::InvalidBitSize(Key::BITS)); };
+        let bits: u32 = length.bits();
+        loop {
+            let offset: Key = self.get_random_bits(bits);
+            if offset < length { return Ok(range.start + offset); }
+        }
     }
-    fn get_random_key_range(&mut self, range: std::ops::Range<Key>, ensure_odd: bool) -> Key {
-        self.get_random_key(ensure_odd) % (range.end - range.start - Key::ONE) + range.start
+    #[inline]
+    fn key_bits(&self) -> u32 {
+        (self.key_byte_size as u32) << 3
     }
     // An even number will correctly fail the test, but it's a good idea to just
     // avoid passing in an even number anyway
     fn miller_rabin_prime_test(&mut self, num: Key, iterations: u8) -> bool {
         // If it's even and not 2, it's not a prime
         if num < Key::FOUR { return num == Key::TWO || num == Key::THREE; }
+        if (num & Key::ONE) == Key::ZERO { return false; }
     
         // Find a 2^e * m = num
         let mut m: Key = num - Key::ONE;
     
         while (m & Key::ONE) == Key::ZERO {
-            m = m >> Key::ONE;
+            m >>= Key::ONE;
         }
     
+        let context: MontgomeryContext = MontgomeryContext::new_unchecked(num);
         for _iter in 0..iterations {
-            let base: Key = self.get_random_key_range(Key::TWO..(num - Key::ONE), false);
-            if !number_passes_miller_rabin(m, num, base) { return false; }
+            // Any 2 <= base <= num - 2. The range is never empty, since num >= 5 here
+            let Ok(base) = self.get_random_in_range(Key::TWO..(num - Key::ONE)) else { return false; };
+            self.stats.miller_rabin_rounds += 1;
+            if !number_passes_miller_rabin(m, &context, base) { return false; }
         }
     
-        return true;
+        true
     }
     
-    pub fn get_random_prime(&mut self, iterations: u8) -> Key {
-        
+    /// Our default primality check: Baillie-PSW, plus as many random-base Miller-Rabin
+    /// rounds as FIPS 186-5 asks for at this size
+    pub fn is_probable_prime(&mut self, num: Key) -> Result<bool, Error> {
+        if num.bits() > MAX_MODULUS_BITS { return Err(Error::InvalidModulus); }
+        Ok(self.probable_prime(num))
+    }
+    fn probable_prime(&mut self, num: Key) -> bool {
+        let rounds_before: u64 = self.stats.miller_rabin_rounds;
+        // Baillie-PSW starts with a base 2 Miller-Rabin round
+        self.stats.miller_rabin_rounds += 1;
+        let prime: bool = baillie_psw(num) && self.miller_rabin_prime_test(num, miller_rabin_rounds(num.bits()));
+        if !prime {
+            self.stats.rounds_on_composites += self.stats.miller_rabin_rounds - rounds_before;
+        }
+        prime
+    }
+    // Count a candidate from a prime search, given whether trial division ruled it out and,
+    // if it didn't, whether it passed the primality tests
+    fn record_candidate(&mut self, trial_division_passed: bool, prime: bool) {
+        self.stats.candidates += 1;
+        if !trial_division_passed {
+            self.stats.rejected_by_trial_division += 1;
+        }
+        else if !prime {
+            self.stats.rejected_by_primality_test += 1;
+        }
+        else {
+            self.stats.primes_found += 1;
+        }
+    }
+
+    /// Search upwards from one random odd starting point instead of drawing a fresh random number
+    /// for every candidate. The residues of the candidate mod every small prime are kept as machine
+    /// words, so stepping to the next odd number only costs a few u64 additions, and the bignum
+    /// primality test only runs on candidates that no small prime divides
+    pub fn get_random_prime(&mut self) -> Result<Key, Error> {
+        self.get_random_n_bit_prime(self.key_bits())
+    }
+    /// A prime of exactly this many bits, with the top two set like get_random_n_bit_key
+    pub fn get_random_n_bit_prime(&mut self, bits: u32) -> Result<Key, Error> {
         loop {
-            // Make sure key is odd
-            let candidate: Key = self.get_random_key(true);
-
-            let mut valid: bool = true;
-            // Check if it's divisible by the first few hundred prime factors
-            // If it is, then it can't itself be prime
-            for prime in 0..primes::FIRST_PRIMES.len() {
-                let key: Key = Key::from(prime);
-                // Can't be divisible by numbers greater than it
-                if candidate * candidate > key { break; }
-                if candidate % key == Key::ZERO {
-                    valid = false;
-                    break;
+            let mut candidate: Key = self.get_random_n_bit_key(bits)? | Key::ONE;
+            let mut residues = SmallPrimeResidues::new(candidate);
+
+            // Once we step past the requested size, start over from a new random number
+            while candidate.bits() <= bits {
+                let trial_division_passed: bool = !residues.has_small_factor(candidate);
+                let prime: bool = trial_division_passed && self.probable_prime(candidate);
+                self.record_candidate(trial_division_passed, prime);
+                if prime { return Ok(candidate); }
+                candidate += Key::TWO;
+                residues.advance(2);
+            }
+        }
+    }
+    /// Generate a safe prime p = 2q + 1 of the key size, along with its Sophie Germain prime q.
+    /// Same incremental search as get_random_prime, but p is kept at 3 (mod 4) so q stays odd,
+    /// and the sieve throws out candidates where either p or q has a small factor
+    pub fn get_random_safe_prime(&mut self) -> Result<SafePrime, Error> {
+        let max_bits: u32 = self.key_bits();
+
+        loop {
+            let mut candidate: Key = self.get_random_key()? | Key::THREE;
+            let mut residues = SmallPrimeResidues::new(candidate);
+
+            while candidate.bits() <= max_bits {
+                let trial_division_passed: bool = !residues.rules_out_safe_prime(candidate);
+                let sophie_germain: Key = candidate >> Key::ONE;
+                // q is the smaller number, so test it first
+                let prime: bool = trial_division_passed
+                    && self.probable_prime(sophie_germain) && self.probable_prime(candidate);
+                self.record_candidate(trial_division_passed, prime);
+                if prime { return Ok(SafePrime { prime: candidate, sophie_germain }); }
+                candidate += Key::FOUR;
+                residues.advance(4);
+            }
+        }
+    }
+    /// Generate a prime p of the key size such that p - 1 has a prime factor q of
+    /// subgroup_byte_size bytes, plus a generator of the order q subgroup.
+    /// This follows the shape of FIPS 186-4 A.1.1.2: pick q, then try random p = 1 (mod 2q)
+    pub fn get_random_subgroup_primes(&mut self, subgroup_byte_size: usize) -> Result<SubgroupPrimes, Error> {
+        // q needs to be noticeably smaller than p, or there won't be any room to search for p in
+        if subgroup_byte_size >= self.key_byte_size { return Err(Error::InvalidSubgroupSize(subgroup_byte_size)); }
+        let max_bits: u32 = self.key_bits();
+
+        loop {
+            let subgroup_order: Key = self.get_random_n_bit_prime((subgroup_byte_size as u32) << 3)?;
+            let double_order: Key = subgroup_order << Key::ONE;
+            // Every p = 2qk + 1 with exactly max_bits bits, so 2^(max_bits - 1) <= 2qk + 1 < 2^max_bits
+            let multipliers = ((Key::ONE << (max_bits - 1)) + double_order - Key::TWO) / double_order
+                ..(((Key::ONE << max_bits) - Key::TWO) / double_order + Key::ONE);
+
+            // Give up on this q after a while, in case it has few matching p's
+            for _attempt in 0..(4 * max_bits) {
+                let prime: Key = double_order * self.get_random_in_range(multipliers.clone())? + Key::ONE;
+                let passed: bool = self.probable_prime(prime);
+                self.record_candidate(true, passed);
+                if !passed { continue; }
+
+                // Any h^((p - 1) / q) other than 1 generates the subgroup of order q
+                let cofactor: Key = (prime - Key::ONE) / subgroup_order;
+                let mut base: Key = Key::TWO;
+                loop {
+                    let generator: Key = bigmod_unchecked(base, cofactor, prime);
+                    if generator != Key::ONE {
+                        return Ok(SubgroupPrimes { prime, subgroup_order, generator });
+                    }
+                    base += Key::ONE;
                 }
             }
-            if valid && self.miller_rabin_prime_test(candidate, iterations) { return candidate; }
         }
     }
-    // Get a random prime different from the given number
-    pub fn get_different_random_prime(&mut self, iterations: u8, last_prime: Key) -> Key {
-        let mut prime: Key = self.get_random_prime(iterations);
-        while prime == last_prime { prime = self.get_random_prime(iterations); }
-        return prime;
+    /// Get a random prime different from the given number
+    pub fn get_different_random_prime(&mut self, last_prime: Key) -> Result<Key, Error> {
+        let mut prime: Key = self.get_random_prime()?;
+        while prime == last_prime { prime = self.get_random_prime()?; }
+        Ok(prime)
     }
 
-    // Generate a random number coprime to the given key
-    pub fn gen_random_coprime(&mut self, coprime: Key) -> Key {
+    /// Generate a random number coprime to the given key
+    pub fn gen_random_coprime(&mut self, coprime: Key) -> Result<Key, Error> {
+        // Only 1 and -1 are coprime to zero, and we'd never draw them
+        if coprime == Key::ZERO { return Err(Error::NoCoprimes); }
         loop {
-            let prime: Key = self.get_random_key(false);
-            if are_coprime(coprime, prime) { return prime; }
+            let prime: Key = self.get_random_key()?;
+            if are_coprime(coprime, prime) { return Ok(prime); }
         }
     }
-    // Generate a random number min < N < max that is coprime with coprimme
-    pub fn gen_random_coprime_number_in_range(&mut self, min: Key, max: Key, coprime: Key) -> Key {
+    /// Generate a uniformly random number min <= N < max that is coprime with coprime
+    pub fn gen_random_coprime_number_in_range(&mut self, min: Key, max: Key, coprime: Key) -> Result<Key, Error> {
+        if coprime == Key::ZERO { return Err(Error::NoCoprimes); }
         loop {
-            let prime: Key = self.get_random_key_range(min..max, false);
-            if are_coprime(coprime, prime) { return prime; }
+            let prime: Key = self.get_random_in_range(min..max)?;
+            if are_coprime(coprime, prime) { return Ok(prime); }
+        }
+    }
+
+    // A prime for RSA, which also needs p - 1 to be coprime with the public exponent
+    fn get_rsa_prime(&mut self, public: Key) -> Result<Key, Error> {
+        loop {
+            let prime: Key = self.get_random_prime()?;
+            if are_coprime(prime - Key::ONE, public) { return Ok(prime); }
+        }
+    }
+    /// Generate an RSA key following FIPS 186-5 Appendix A.1.3. The modulus has exactly twice the
+    /// bits of a key, the primes are at least 2^(nlen/2 - 100) apart, and the private exponent is
+    /// the inverse of the public exponent mod lambda(n) = lcm(p - 1, q - 1), and bigger than 2^(nlen/2)
+    pub fn get_rsa_keys(&mut self) -> Result<RSAKeyInfo, Error> {
+        check_rsa_prime_size(self.key_byte_size)?;
+        let public: Key = Key::from(PUBLIC_EXPONENT);
+        let prime_bits: u32 = self.key_bits();
+
+        loop {
+            // Anything that could rebuild the private key gets wiped once we're done with it,
+            // including the attempts we throw away
+            let prime_a: Zeroizing<Key> = Zeroizing::new(self.get_rsa_prime(public)?);
+            let mut prime_b: Zeroizing<Key> = Zeroizing::new(self.get_rsa_prime(public)?);
+            while (*prime_a - *prime_b).abs() <= Key::ONE << prime_bits.saturating_sub(100) {
+                *prime_b = self.get_rsa_prime(public)?;
+            }
+
+            let shared: Key = *prime_a * *prime_b;
+            let lambda: Zeroizing<Key> = Zeroizing::new(carmichael_lambda(*prime_a, *prime_b));
+            let Ok(inverse) = get_modular_inverse_ct(public, *lambda) else { continue; };
+            let private: Zeroizing<Key> = Zeroizing::new(inverse);
+            // A small private exponent is open to Wiener's attack. This essentially never
+            // happens, but the standard says to start over if it does
+            if *private <= Key::ONE << prime_bits { continue; }
+
+            let keys = RSAKeyInfo { public, private: *private, shared, prime_a: *prime_a, prime_b: *prime_b };
+            // Never hand out a key that doesn't validate
+            if keys.validate().is_ok() { return Ok(keys); }
         }
     }
 }
 
-#[derive(Clone, Copy)]
+/// Not Copy, so the private half can't get duplicated without anyone noticing. Its secrets are wiped on drop.
+/// Key itself is Copy though, so reading a secret out of a field (`let d: Key = keys.private`) makes a copy
+/// that the wipe never reaches. Keep those in a Zeroizing, or borrow the field instead
+#[derive(Clone)]
 pub struct RSAKeyInfo {
+    /// The public exponent e
     pub public: Key,
+    /// The private exponent d
     pub private: Key,
+    /// The modulus n
+    pub shared: Key,
+    /// The two primes whose product is the shared modulus
+    pub prime_a: Key,
+    /// The other prime, the same size as prime_a
+    pub prime_b: Key
+}
+/// The half of an RSA key that's safe to hand out
+#[derive(Clone, Copy)]
+pub struct RSAPublicKey {
+    /// The public exponent e
+    pub public: Key,
+    /// The modulus n
     pub shared: Key
 }
+
+/// Why a key failed validation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A component is zero or negative
+    NotPositive,
+    /// The modulus is too big for bigmod to work with
+    ModulusTooLarge,
+    /// The modulus is too small to be the product of two primes past the small prime table
+    ModulusTooSmall,
+    /// The modulus is even
+    EvenModulus,
+    /// The modulus is divisible by a prime from primes::FIRST_PRIMES
+    ModulusHasSmallFactor,
+    /// The modulus is prime, so it has no secret factors
+    ModulusIsPrime,
+    /// The modulus is a perfect power, like p^2
+    ModulusIsPerfectPower,
+    /// The public exponent is even, or not between 2^16 and 2^256
+    PublicExponentOutOfRange,
+    /// The private exponent is at most 2^(nlen/2), or not less than the modulus
+    PrivateExponentOutOfRange,
+    /// prime_a * prime_b isn't the modulus
+    ModulusMismatch,
+    /// One of the primes isn't prime
+    CompositePrime,
+    /// The primes have different bit lengths, or their product has the wrong length
+    KeySizeMismatch,
+    /// |prime_a - prime_b| is small enough for Fermat's method to factor the modulus
+    PrimesTooClose,
+    /// public * private isn't 1 (mod lambda(shared))
+    ExponentMismatch
+}
+#[cfg(feature = "std")]
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            ValidationError::NotPositive => "key component is not positive",
+            ValidationError::ModulusTooLarge => "modulus is too large",
+            ValidationError::ModulusTooSmall => "modulus is too small",
+            ValidationError::EvenModulus => "modulus is even",
+            ValidationError::ModulusHasSmallFactor => "modulus has a small prime factor",
+            ValidationError::ModulusIsPrime => "modulus is prime",
+            ValidationError::ModulusIsPerfectPower => "modulus is a perfect power",
+            ValidationError::PublicExponentOutOfRange => "public exponent is out of range",
+            ValidationError::PrivateExponentOutOfRange => "private exponent is out of range",
+            ValidationError::ModulusMismatch => "modulus is not the product of the primes",
+            ValidationError::CompositePrime => "prime factor is not prime",
+            ValidationError::KeySizeMismatch => "prime and modulus sizes do not match",
+            ValidationError::PrimesTooClose => "prime factors are too close together",
+            ValidationError::ExponentMismatch => "exponents are not inverses mod lambda(n)"
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// The public exponent for generated keys. FIPS 186-5 requires an odd 2^16 < e < 2^256
+pub const PUBLIC_EXPONENT: u32 = 65537;
+
+/// bigmod multiplies into a WideKey, but adds two numbers below the modulus, and that sum has to
+/// fit in a (signed) Key. Anything bigger than this is rejected before it gets near bigmod
+pub const MAX_MODULUS_BITS: u32 = Key::BITS - 2;
+
+// Floor of the k-th root of a positive key, using Newton's method
+fn iroot(num: Key, k: u32) -> Key {
+    if num < Key::TWO { return num; }
+
+    let mut root: Key = Key::ONE << num.bits().div_ceil(k);
+    let k_key: Key = Key::from(k);
+    loop {
+        // A root^(k - 1) too big for a Key is bigger than num, so the quotient is zero
+        let quotient: Key = root.checked_pow(k - 1).map_or(Key::ZERO, |power| num / power);
+        let next: Key = ((k_key - Key::ONE) * root + quotient) / k_key;
+        if next >= root { return root; }
+        root = next;
+    }
+}
+// Is num = b^k for some k > 1? Only checks bases bigger than the small prime table,
+// so divide out small factors before calling this
+fn is_perfect_power(num: Key) -> bool {
+    let bits: u32 = num.bits();
+    let largest_prime: u32 = primes::FIRST_PRIMES[primes::FIRST_PRIMES.len() - 1] as u32;
+    // Every base left is bigger than the largest small prime, so the exponent is bounded
+    let max_exponent: u32 = bits / (u32::BITS - largest_prime.leading_zeros() - 1);
+
+    // It's enough to check prime exponents
+    if is_perfect_square(num) { return true; }
+    for prime in primes::FIRST_PRIMES {
+        let k: u32 = prime as u32;
+        if k > max_exponent { break; }
+        if iroot(num, k).pow(k) == num { return true; }
+    }
+    false
+}
+// Carmichael's function for a product of two distinct primes, lcm(p - 1, q - 1)
+fn carmichael_lambda(prime_a: Key, prime_b: Key) -> Key {
+    let a: Key = prime_a - Key::ONE;
+    let b: Key = prime_b - Key::ONE;
+    (a / gcd(a, b)) * b
+}
+
+impl RSAPublicKey {
+    /// Partial public key validation (SP 800-56B 6.4.2.2). Anything that passes is
+    /// safe to feed to bigmod, even if we can't prove the modulus is a real RSA modulus
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.shared <= Key::ZERO || self.public <= Key::ZERO { return Err(ValidationError::NotPositive); }
+        if self.shared.bits() > MAX_MODULUS_BITS { return Err(ValidationError::ModulusTooLarge); }
+        // The modulus has to at least be the product of two primes past the small prime table
+        let largest_prime: Key = Key::from(primes::FIRST_PRIMES[primes::FIRST_PRIMES.len() - 1]);
+        if self.shared <= largest_prime * largest_prime { return Err(ValidationError::ModulusTooSmall); }
+        if (self.shared & Key::ONE) == Key::ZERO { return Err(ValidationError::EvenModulus); }
+
+        // SP 800-56B and FIPS 186-5 both want an odd 2^16 < e < 2^256
+        if self.public <= (Key::ONE << 16u32) || self.public.bits() > 256 || self.public >= self.shared
+            || (self.public & Key::ONE) == Key::ZERO {
+            return Err(ValidationError::PublicExponentOutOfRange);
+        }
+
+        let mut remainders: [u64; primes::PRIME_PRODUCT_COUNT] = [0; primes::PRIME_PRODUCT_COUNT];
+        rem_u64_batch(self.shared, &primes::PRIME_PRODUCTS, &mut remainders);
+        for (product, remainder) in primes::PRIME_PRODUCTS.into_iter().zip(remainders) {
+            if primes::gcd_u64(remainder, product) != 1 { return Err(ValidationError::ModulusHasSmallFactor); }
+        }
+        if baillie_psw(self.shared) { return Err(ValidationError::ModulusIsPrime); }
+        if is_perfect_power(self.shared) { return Err(ValidationError::ModulusIsPerfectPower); }
+
+        Ok(())
+    }
+}
+
+impl RSAKeyInfo {
+    /// The public half of the key
+    pub fn public_key(&self) -> RSAPublicKey {
+        RSAPublicKey { public: self.public, shared: self.shared }
+    }
+    /// q^-1 mod p, which the CRT form of the private key needs
+    pub fn crt_coefficient(&self) -> Result<Key, NotInvertible> {
+        get_modular_inverse_ct(self.prime_b, self.prime_a)
+    }
+
+    /// Check that the key is consistent, following the RSA key-pair validation in SP 800-56B 6.4.1
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.private <= Key::ZERO || self.prime_a <= Key::ZERO || self.prime_b <= Key::ZERO {
+            return Err(ValidationError::NotPositive);
+        }
+        // Make sure multiplying the primes can't overflow before we do it
+        if self.prime_a.bits() + self.prime_b.bits() > MAX_MODULUS_BITS + 1 { return Err(ValidationError::ModulusTooLarge); }
+        if self.prime_a * self.prime_b != self.shared { return Err(ValidationError::ModulusMismatch); }
+
+        self.public_key().validate()?;
+
+        // Both primes should be the same size, and big enough that the modulus has exactly twice
+        // their bits: p >= sqrt(2) * 2^(nlen/2 - 1), or equivalently p^2 >= 2^(nlen - 1)
+        let prime_bits: u32 = self.prime_a.bits();
+        let shared_bits: u32 = self.shared.bits();
+        // Check the sizes match before shifting by them, since a lopsided key can have a prime_a
+        // too big for 2^(2 * prime_bits - 1) to fit in a Key
+        if self.prime_b.bits() != prime_bits || shared_bits != 2 * prime_bits {
+            return Err(ValidationError::KeySizeMismatch);
+        }
+        let min_square: Key = Key::ONE << (2 * prime_bits - 1);
+        if self.prime_a * self.prime_a < min_square || self.prime_b * self.prime_b < min_square {
+            return Err(ValidationError::KeySizeMismatch);
+        }
+        // |p - q| has to be more than 2^(nlen/2 - 100), or Fermat factorization finds them
+        let distance: Key = (self.prime_a - self.prime_b).abs();
+        if distance <= Key::ONE << (shared_bits >> 1).saturating_sub(100) { return Err(ValidationError::PrimesTooClose); }
+
+        if !baillie_psw(self.prime_a) || !baillie_psw(self.prime_b) {
+            return Err(ValidationError::CompositePrime);
+        }
+
+        // SP 800-56B wants 2^(nlen/2) < d < lambda(n), and that's what we generate. But OpenSSL and
+        // older tools reduce d mod phi(n) instead, which is just as correct, so only insist on d < n
+        if self.private <= Key::ONE << (shared_bits >> 1) || self.private >= self.shared {
+            return Err(ValidationError::PrivateExponentOutOfRange);
+        }
+        let lambda: Key = carmichael_lambda(self.prime_a, self.prime_b);
+        if mul_mod(self.public % lambda, self.private % lambda, lambda) != Key::ONE {
+            return Err(ValidationError::ExponentMismatch);
+        }
+
+        Ok(())
+    }
+}
+impl Drop for RSAKeyInfo {
+    fn drop(&mut self) {
+        self.private.zeroize();
+        self.prime_a.zeroize();
+        self.prime_b.zeroize();
+    }
+}
+// Only the public half gets printed, so keys can go in logs without leaking
+#[cfg(feature = "std")]
 fn format_keys(keys: &RSAKeyInfo, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "( public: {}, private: {}, shared: {} )", keys.public, keys.private, keys.shared)
+    write!(f, "( public: {}, private: [redacted], shared: {} )", keys.public, keys.shared)
 }
 #[cfg(feature = "std")]
 impl Debug for RSAKeyInfo {
@@ -248,4 +1124,250 @@ impl Display for RSAKeyInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         format_keys(self, f)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Handler = NumberHandler<ThreadRng>;
+
+    // The FIPS 186-5 A.1.3 rules, checked from scratch rather than with validate()
+    fn check_fips_186_5(keys: &RSAKeyInfo, nlen: u32) {
+        let half: u32 = nlen / 2;
+        assert_eq!(keys.shared.bits(), nlen);
+        assert!(keys.prime_a * keys.prime_b == keys.shared);
+        for prime in [keys.prime_a, keys.prime_b] {
+            // p >= sqrt(2) * 2^(nlen/2 - 1), squared so it stays in integers
+            assert!(prime * prime >= Key::ONE << (nlen - 1));
+            assert_eq!(prime.bits(), half);
+            assert_eq!(passes_baillie_psw(prime), Ok(true));
+        }
+        if half > 100 {
+            assert!((keys.prime_a - keys.prime_b).abs() > Key::ONE << (half - 100));
+        }
+
+        assert!(keys.public > Key::ONE << 16u32 && keys.public < Key::ONE << 256u32);
+        assert_eq!(keys.public & Key::ONE, Key::ONE);
+        let (a, b): (Key, Key) = (keys.prime_a - Key::ONE, keys.prime_b - Key::ONE);
+        let lambda: Key = a / gcd(a, b) * b;
+        assert!(keys.private > Key::ONE << half && keys.private < lambda);
+        assert_eq!(mul_mod(keys.public, keys.private, lambda), Key::ONE);
+    }
+
+    #[test]
+    fn generated_keys_follow_fips_186_5() {
+        for (key_byte_size, count) in [(16, 8), (32, 4), (64, 2), (128, 1)] {
+            let mut handler: Handler = NumberHandler::new(key_byte_size).unwrap();
+            for _ in 0..count {
+                let keys: RSAKeyInfo = handler.get_rsa_keys().unwrap();
+                check_fips_186_5(&keys, (key_byte_size as u32) << 4);
+                assert_eq!(keys.validate(), Ok(()));
+            }
+        }
+    }
+
+    // The next prime at or after the candidate, skipping any where 65537 divides p - 1 so the
+    // public exponent always has an inverse
+    fn next_prime(handler: &mut Handler, start: Key) -> Key {
+        let mut candidate: Key = start | Key::ONE;
+        while !handler.is_probable_prime(candidate).unwrap() || rem_u64(candidate - Key::ONE, PUBLIC_EXPONENT as u64) == 0 {
+            candidate += Key::TWO;
+        }
+        candidate
+    }
+    // A prime in the range, well away from both ends
+    fn prime_in_range(handler: &mut Handler, range: Range<Key>) -> Key {
+        let quarter: Key = (range.end - range.start) >> 2u32;
+        let start: Key = handler.get_random_in_range((range.start + quarter)..(range.end - quarter)).unwrap();
+        next_prime(handler, start)
+    }
+    fn key_from_primes(prime_a: Key, prime_b: Key) -> RSAKeyInfo {
+        let public: Key = Key::from(PUBLIC_EXPONENT);
+        let private: Key = get_modular_inverse(public, carmichael_lambda(prime_a, prime_b)).unwrap();
+        RSAKeyInfo { public, private, shared: prime_a * prime_b, prime_a, prime_b }
+    }
+
+    #[test]
+    fn validation_rejects_keys_that_break_fips_186_5() {
+        // 256-bit primes for a 512-bit modulus
+        let mut handler: Handler = NumberHandler::new(32).unwrap();
+        let bits: u32 = 256;
+        let low: Key = Key::ONE << (bits - 1);
+        // sqrt(2) * 2^(bits - 1) is just under 1.5 * 2^(bits - 1)
+        let lower_half: Range<Key> = low..(low + (low >> 2u32));
+        let upper_half: Range<Key> = (low + (low >> 1u32))..(Key::ONE << bits);
+        let good: RSAKeyInfo = handler.get_rsa_keys().unwrap();
+
+        // Both primes below sqrt(2) * 2^(nlen/2 - 1), so the modulus is a bit short of nlen
+        let short: RSAKeyInfo = key_from_primes(prime_in_range(&mut handler, lower_half.clone()), prime_in_range(&mut handler, lower_half.clone()));
+        assert_eq!(short.shared.bits(), 2 * bits - 1);
+        assert_eq!(short.validate(), Err(ValidationError::KeySizeMismatch));
+        // The modulus has the full nlen bits, but one prime is still too small
+        let lopsided: RSAKeyInfo = key_from_primes(prime_in_range(&mut handler, lower_half.clone()), next_prime(&mut handler, (Key::ONE << bits) - (low >> 4u32)));
+        assert_eq!(lopsided.shared.bits(), 2 * bits);
+        assert_eq!(lopsided.validate(), Err(ValidationError::KeySizeMismatch));
+        // Primes of different sizes
+        let uneven: RSAKeyInfo = key_from_primes(good.prime_a, next_prime(&mut handler, Key::ONE << (bits + 1)));
+        assert_eq!(uneven.validate(), Err(ValidationError::KeySizeMismatch));
+        // So lopsided that 2^(2 * prime_a's bits) doesn't fit in a Key. This has to be rejected, not panic
+        // prime_a only has to get past the modulus checks, so it doesn't need to be prime, just free
+        // of small factors, which is much quicker to find at 4000 bits
+        let mut huge: Key = (Key::ONE << 3999u32) + Key::ONE;
+        let mut remainders: [u64; primes::PRIME_PRODUCT_COUNT] = [0; primes::PRIME_PRODUCT_COUNT];
+        loop {
+            rem_u64_batch(huge, &primes::PRIME_PRODUCTS, &mut remainders);
+            if primes::PRIME_PRODUCTS.iter().zip(remainders).all(|(product, remainder)| primes::gcd_u64(remainder, *product) == 1) { break; }
+            huge += Key::TWO;
+        }
+        let tiny: Key = next_prime(&mut handler, Key::ONE << 39u32);
+        let lopsided: RSAKeyInfo = RSAKeyInfo { public: good.public, private: good.private, shared: huge * tiny, prime_a: huge, prime_b: tiny };
+        assert_eq!(lopsided.validate(), Err(ValidationError::KeySizeMismatch));
+
+        // |p - q| <= 2^(nlen/2 - 100)
+        let prime_a: Key = prime_in_range(&mut handler, upper_half.clone());
+        let close: RSAKeyInfo = key_from_primes(prime_a, next_prime(&mut handler, prime_a + Key::TWO));
+        assert_eq!(close.validate(), Err(ValidationError::PrimesTooClose));
+
+        // e outside 2^16 < e < 2^256, or even
+        for public in [Key::THREE, Key::from(65535u32), Key::from(65538u32), (Key::ONE << 256u32) + Key::ONE] {
+            let mut keys: RSAKeyInfo = good.clone();
+            keys.public = public;
+            assert_eq!(keys.public_key().validate(), Err(ValidationError::PublicExponentOutOfRange));
+            assert_eq!(keys.validate(), Err(ValidationError::PublicExponentOutOfRange));
+        }
+
+        // d <= 2^(nlen/2), or d >= n
+        let mut keys: RSAKeyInfo = good.clone();
+        keys.private = Key::ONE << bits;
+        assert_eq!(keys.validate(), Err(ValidationError::PrivateExponentOutOfRange));
+        keys.private = good.shared + Key::ONE;
+        assert_eq!(keys.validate(), Err(ValidationError::PrivateExponentOutOfRange));
+        // d * e != 1 (mod lambda(n))
+        keys.private = good.private + Key::TWO;
+        assert_eq!(keys.validate(), Err(ValidationError::ExponentMismatch));
+        // But d mod phi(n) instead of lambda(n) is still the right inverse
+        keys.private = good.private + carmichael_lambda(good.prime_a, good.prime_b);
+        if keys.private < good.shared { assert_eq!(keys.validate(), Ok(())); }
+
+        // A composite "prime" that's otherwise the right shape
+        let composite: Key = loop {
+            let product: Key = handler.get_random_n_bit_prime(bits / 2).unwrap() * handler.get_random_n_bit_prime(bits / 2).unwrap();
+            if product * product >= Key::ONE << (2 * bits - 1) { break product; }
+        };
+        let keys: RSAKeyInfo = RSAKeyInfo { prime_a: composite, shared: composite * good.prime_b, ..good.clone() };
+        assert_eq!(keys.validate(), Err(ValidationError::CompositePrime));
+        // And a modulus that isn't the product of the primes
+        let keys: RSAKeyInfo = RSAKeyInfo { shared: good.shared + Key::TWO, ..good.clone() };
+        assert_eq!(keys.validate(), Err(ValidationError::ModulusMismatch));
+    }
+
+    #[test]
+    fn public_validation_rejects_bad_moduli() {
+        let public: Key = Key::from(PUBLIC_EXPONENT);
+        let mut handler: Handler = NumberHandler::new(32).unwrap();
+        let prime: Key = handler.get_random_prime().unwrap();
+        let check = |shared: Key| RSAPublicKey { public, shared }.validate();
+
+        assert_eq!(check(Key::ZERO), Err(ValidationError::NotPositive));
+        assert_eq!(check(Key::ONE << MAX_MODULUS_BITS), Err(ValidationError::ModulusTooLarge));
+        assert_eq!(check(Key::from(1_000_003u32)), Err(ValidationError::ModulusTooSmall));
+        assert_eq!(check(prime << 1u32), Err(ValidationError::EvenModulus));
+        assert_eq!(check(prime * Key::THREE), Err(ValidationError::ModulusHasSmallFactor));
+        assert_eq!(check(prime), Err(ValidationError::ModulusIsPrime));
+        assert_eq!(check(prime * prime), Err(ValidationError::ModulusIsPerfectPower));
+    }
+
+    #[test]
+    fn rejects_bad_inputs() {
+        assert_eq!(NumberHandler::new(0).err(), Some(Error::InvalidKeySize(0)));
+        assert_eq!(NumberHandler::new((MAX_MODULUS_BITS as usize >> 3) + 1).err(), Some(Error::InvalidKeySize(512)));
+        assert_eq!(check_rsa_prime_size(1), Err(Error::InvalidKeySize(1)));
+        assert_eq!(check_rsa_prime_size(256), Err(Error::InvalidKeySize(256)));
+        assert_eq!(NumberHandler::new(1).unwrap().get_rsa_keys().err(), Some(Error::InvalidKeySize(1)));
+
+        assert_eq!(bigmod(Key::TWO, Key::TEN, Key::ZERO), Err(Error::InvalidModulus));
+        assert_eq!(bigmod(Key::TWO, Key::TEN, -Key::SEVEN), Err(Error::InvalidModulus));
+        assert_eq!(bigmod(Key::TWO, Key::TEN, Key::ONE << MAX_MODULUS_BITS), Err(Error::InvalidModulus));
+        assert_eq!(bigmod(Key::TWO, -Key::ONE, Key::SEVEN), Err(Error::NegativeExponent));
+        assert_eq!(passes_baillie_psw(Key::ONE << MAX_MODULUS_BITS), Err(Error::InvalidModulus));
+        assert_eq!(get_modular_inverse(Key::from(6u32), Key::from(9u32)), Err(NotInvertible));
+        assert_eq!(get_modular_inverse_ct(Key::from(6u32), Key::from(9u32)), Err(NotInvertible));
+
+        let mut handler = NumberHandler::new(8).unwrap();
+        assert_eq!(handler.get_random_in_range(Key::TEN..Key::TEN), Err(Error::EmptyRange));
+        assert_eq!(handler.get_random_in_range(Key::TEN..Key::ONE), Err(Error::EmptyRange));
+        assert_eq!(handler.get_random_in_range(Key::MIN..Key::MAX), Err(Error::InvalidBitSize(Key::BITS)));
+        assert_eq!(handler.gen_random_coprime(Key::ZERO), Err(Error::NoCoprimes));
+        assert_eq!(handler.gen_random_coprime_number_in_range(Key::ONE, Key::TEN, Key::ZERO), Err(Error::NoCoprimes));
+        assert!(matches!(handler.get_random_subgroup_primes(8), Err(Error::InvalidSubgroupSize(8))));
+        assert_eq!(handler.is_probable_prime(Key::ONE << MAX_MODULUS_BITS), Err(Error::InvalidModulus));
+    }
+
+    #[test]
+    fn inverses_agree_with_each_other() {
+        let mut handler: Handler = NumberHandler::new(8).unwrap();
+        for round in 0..200 {
+            // Odd and even moduli, and a shared factor of 3 every few rounds so there's no inverse
+            let shared_factor: bool = round % 4 == 3;
+            let mut modulus: Key = handler.get_random_n_bit_key(300).unwrap();
+            modulus = if round % 2 == 0 { modulus | Key::ONE } else { modulus & !Key::ONE };
+            let mut a: Key = handler.get_random_in_range(-modulus..(modulus << 1u32)).unwrap();
+            if shared_factor {
+                modulus *= Key::THREE;
+                a *= Key::THREE;
+            }
+
+            let expected: Result<Key, NotInvertible> = get_modular_inverse(a, modulus);
+            assert_eq!(get_modular_inverse_ct(a, modulus), expected);
+            if shared_factor { assert_eq!(expected, Err(NotInvertible)); }
+            if let Ok(inverse) = expected {
+                assert!(inverse > Key::ZERO && inverse < modulus);
+                assert_eq!(mul_mod(a.rem_euclid(modulus), inverse, modulus), Key::ONE);
+            }
+
+            // Both parities, and sometimes a common power of two
+            let b: Key = handler.get_random_n_bit_key(200).unwrap() << (round % 3);
+            let positive: Key = (a.rem_euclid(modulus) + Key::ONE) << (round % 5);
+            let (divisor, x, y) = binary_extended_gcd(positive, b);
+            assert_eq!(divisor, gcd(positive, b));
+            assert_eq!(positive * x + b * y, divisor);
+        }
+    }
+
+    #[test]
+    fn random_n_bit_keys_have_exactly_n_bits() {
+        let mut handler = NumberHandler::new(8).unwrap();
+        for bits in 2..=MAX_MODULUS_BITS {
+            for _ in 0..4 {
+                let key: Key = handler.get_random_n_bit_key(bits).unwrap();
+                assert_eq!(key.bits(), bits);
+                assert!(key.bit(bits - 1) && key.bit(bits - 2));
+            }
+        }
+        // Everything below the top two bits is random, so across enough keys every one of them comes up
+        let mut seen: Key = Key::ZERO;
+        for _ in 0..64 {
+            seen |= handler.get_random_n_bit_key(MAX_MODULUS_BITS).unwrap();
+        }
+        assert_eq!(seen, (Key::ONE << MAX_MODULUS_BITS) - Key::ONE);
+
+        for bits in [0, 1, MAX_MODULUS_BITS + 1, Key::BITS] {
+            assert_eq!(handler.get_random_n_bit_key(bits), Err(Error::InvalidBitSize(bits)));
+        }
+    }
+
+    #[test]
+    fn keys_wipe_their_secrets_on_drop() {
+        let keys: RSAKeyInfo = NumberHandler::new(16).unwrap().get_rsa_keys().unwrap();
+        let (public, shared): (Key, Key) = (keys.public, keys.shared);
+        let mut slot: core::mem::MaybeUninit<RSAKeyInfo> = core::mem::MaybeUninit::new(keys);
+        // SAFETY: the slot holds a key, and Keys are still valid to read after the drop
+        let left: (Key, Key, Key, Key, Key) = unsafe {
+            slot.assume_init_drop();
+            let dropped: *const RSAKeyInfo = slot.as_ptr();
+            ((*dropped).public, (*dropped).private, (*dropped).shared, (*dropped).prime_a, (*dropped).prime_b)
+        };
+        assert_eq!(left, (public, Key::ZERO, shared, Key::ZERO, Key::ZERO));
+    }
+}
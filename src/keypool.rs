@@ -0,0 +1,508 @@
+/* Keys take a while to generate, so the server will store a pool of keys from which to choose randomly.
+    A worker thread keeps generating new keys in the background and adds them to the pool.
+    We also can't store an unlimited number of keys -- they're way too big. So, once the pool is full,
+    we'll overwrite the oldest keys. Keys are also retired once they're too old or have been handed
+    out too many times, so no key is used forever. Any number of connection handlers can share one pool.
+    The pool can be saved to an encrypted snapshot as it changes and when the server shuts down, and
+    loaded again on startup, so a restart doesn't have to wait for the whole pool to generate again. */
+
+use std::fmt;
+use std::fmt::Display;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Condvar, Mutex, MutexGuard };
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant, SystemTime };
+use rand::prelude::*;
+
+use crate::keygen::RSAKeyInfo;
+use crate::keystore::{ KeyStore, KeyStoreError };
+use crate::zeroize::Zeroizing;
+
+// Snapshots are key stores with every key labelled "pool" and "uses=<times handed out>"
+const SNAPSHOT_LABEL: &str = "pool";
+const USES_LABEL_PREFIX: &str = "uses=";
+
+/// How many keys a pool holds, and when it retires them
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    /// The most keys the pool holds at once
+    pub capacity: usize,
+    /// Keys older than this are retired
+    pub max_age: Duration,
+    /// Keys are retired after being handed out this many times
+    pub max_uses: u32
+}
+impl PoolConfig {
+    /// Ten keys, each used at most 100 times and for at most an hour
+    pub const DEFAULT: PoolConfig = PoolConfig { capacity: 10, max_age: Duration::from_secs(60 * 60), max_uses: 100 };
+}
+
+/// Why a pool or worker couldn't be made with the config it was given
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolConfigError {
+    /// The pool has no room for keys
+    ZeroCapacity,
+    /// Keys would be retired before they were ever handed out
+    ZeroMaxUses,
+    /// Not in `[MIN_CPU_BUDGET, 1]`, or NaN
+    InvalidCpuBudget(f32)
+}
+impl Display for PoolConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolConfigError::ZeroCapacity => write!(f, "a key pool needs room for at least one key"),
+            PoolConfigError::ZeroMaxUses => write!(f, "pooled keys have to be usable at least once"),
+            PoolConfigError::InvalidCpuBudget(budget) => write!(f, "a CPU budget of {} isn't between {} and 1", budget, MIN_CPU_BUDGET)
+        }
+    }
+}
+
+/// Counters since the pool was made, plus a snapshot of how full it is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Keys in the pool that are still usable
+    pub available: usize,
+    /// The most keys the pool holds at once
+    pub capacity: usize,
+    /// Keys put in the pool
+    pub added: u64,
+    /// Keys handed out
+    pub served: u64,
+    /// Retired for being older than max_age
+    pub expired: u64,
+    /// Retired for reaching max_uses
+    pub used_up: u64,
+    /// Overwritten while still usable, because the pool was full
+    pub evicted: u64,
+    /// Requests that gave up waiting on an empty pool
+    pub timeouts: u64
+}
+
+/// A key in the pool, with what's needed to know when to retire it
+#[derive(Clone)]
+pub struct PooledKey<KeyInfo> {
+    /// The key itself
+    pub key: KeyInfo,
+    /// When the key was made, which it expires max_age after
+    pub created: SystemTime,
+    /// How many times the key has been handed out
+    pub uses: u32
+}
+
+struct PoolState<KeyInfo> {
+    // Fixed slots, so retiring a key drops (and wipes) it in place instead of moving other keys around
+    slots: Vec<Option<PooledKey<KeyInfo>>>,
+    stats: PoolStats
+}
+impl<KeyInfo> PoolState<KeyInfo> {
+    fn available(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+    fn retire_expired(&mut self, max_age: Duration) {
+        let now: SystemTime = SystemTime::now();
+        for slot in self.slots.iter_mut() {
+            // A creation time in the future means the clock went backwards, so don't trust it either
+            let expired: bool = slot.as_ref().is_some_and(|pooled| match now.duration_since(pooled.created) {
+                Ok(age) => age >= max_age,
+                Err(_) => true
+            });
+            if expired {
+                *slot = None;
+                self.stats.expired += 1;
+            }
+        }
+    }
+}
+
+/// A fixed number of pre-generated keys, handed out until they're too old or too used.
+/// It's shared between the threads that fill it and the ones taking keys from it
+pub struct KeyPool<KeyInfo> {
+    config: PoolConfig,
+    state: Mutex<PoolState<KeyInfo>>,
+    // Signalled whenever a key is added
+    added: Condvar,
+    // Signalled whenever a key is used up, so the worker can replace it straight away
+    retired: Condvar
+}
+impl<KeyInfo: Clone> KeyPool<KeyInfo> {
+    /// An empty pool, as long as the config leaves room for keys that can be used
+    pub fn new(config: PoolConfig) -> Result<Self, PoolConfigError> {
+        if config.capacity == 0 { return Err(PoolConfigError::ZeroCapacity); }
+        if config.max_uses == 0 { return Err(PoolConfigError::ZeroMaxUses); }
+        let mut slots: Vec<Option<PooledKey<KeyInfo>>> = Vec::new();
+        slots.resize_with(config.capacity, || None);
+        Ok(Self { config, state: Mutex::new(PoolState { slots, stats: PoolStats::default() }), added: Condvar::new(), retired: Condvar::new() })
+    }
+    /// The config the pool was made with
+    pub fn config(&self) -> PoolConfig {
+        self.config
+    }
+
+    // Lock the pool, retiring anything that's expired while nobody was looking
+    fn lock(&self) -> MutexGuard<'_, PoolState<KeyInfo>> {
+        let mut state = self.state.lock().unwrap();
+        state.retire_expired(self.config.max_age);
+        state
+    }
+
+    /// Insert a key made just now
+    pub fn insert_key(&self, key: KeyInfo) {
+        self.insert_key_created_at(key, SystemTime::now());
+    }
+    /// Insert a key made earlier, so it still expires on time
+    pub fn insert_key_created_at(&self, key: KeyInfo, created: SystemTime) {
+        self.insert(PooledKey { key, created, uses: 0 });
+    }
+    /// Insert a key along with how much it's been used already
+    pub fn insert(&self, pooled: PooledKey<KeyInfo>) {
+        let mut state = self.lock();
+        let index: usize = match state.slots.iter().position(|slot| slot.is_none()) {
+            Some(index) => index,
+            None => {
+                // Full, so overwrite the oldest key. Every slot is filled here
+                state.stats.evicted += 1;
+                (0..state.slots.len()).min_by_key(|index| state.slots[*index].as_ref().map(|other| other.created)).unwrap()
+            }
+        };
+        // The key being replaced is dropped here, which wipes it
+        state.slots[index] = Some(pooled);
+        state.stats.added += 1;
+        drop(state);
+        self.added.notify_all();
+    }
+
+    /// A copy of a random key, waiting up to the timeout for one if the pool is empty. A timeout
+    /// too long for an Instant to hold, like Duration::MAX, waits as long as it takes.
+    /// This counts as a use, and the key is retired if that was its last
+    pub fn get_random_timeout(&self, timeout: Duration) -> Option<KeyInfo> {
+        let deadline: Option<Instant> = Instant::now().checked_add(timeout);
+        let mut state = self.lock();
+        while state.available() == 0 {
+            state = match deadline {
+                Some(deadline) => {
+                    let remaining: Duration = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        state.stats.timeouts += 1;
+                        return None;
+                    }
+                    self.added.wait_timeout(state, remaining).unwrap().0
+                },
+                None => self.added.wait(state).unwrap()
+            };
+            state.retire_expired(self.config.max_age);
+        }
+
+        let filled: Vec<usize> = (0..state.slots.len()).filter(|index| state.slots[*index].is_some()).collect();
+        let index: usize = filled[rand::rng().random_range(0..filled.len())];
+        let pooled: &mut PooledKey<KeyInfo> = state.slots[index].as_mut().unwrap();
+        pooled.uses += 1;
+        let key: KeyInfo = pooled.key.clone();
+        if pooled.uses >= self.config.max_uses {
+            state.slots[index] = None;
+            state.stats.used_up += 1;
+            self.retired.notify_all();
+        }
+        state.stats.served += 1;
+        Some(key)
+    }
+
+    /// How many usable keys there are
+    pub fn len(&self) -> usize {
+        self.lock().available()
+    }
+    /// Whether there are no usable keys
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Whether every slot holds a usable key
+    pub fn is_full(&self) -> bool {
+        self.len() == self.config.capacity
+    }
+    /// Wait up to the timeout for a key to be used up. Returns whether the pool is still full
+    pub fn wait_while_full(&self, timeout: Duration) -> bool {
+        let state = self.lock();
+        let (mut state, _) = self.retired.wait_timeout_while(state, timeout, |state| state.available() == self.config.capacity).unwrap();
+        state.retire_expired(self.config.max_age);
+        state.available() == self.config.capacity
+    }
+    /// How long until the oldest key expires, or None if the pool is empty
+    pub fn time_until_next_expiry(&self) -> Option<Duration> {
+        let state = self.lock();
+        let oldest: SystemTime = state.slots.iter().flatten().map(|pooled| pooled.created).min()?;
+        let age: Duration = SystemTime::now().duration_since(oldest).unwrap_or(Duration::ZERO);
+        Some(self.config.max_age.saturating_sub(age))
+    }
+    /// The counters so far, and how full the pool is now
+    pub fn stats(&self) -> PoolStats {
+        let state = self.lock();
+        PoolStats { available: state.available(), capacity: self.config.capacity, ..state.stats }
+    }
+    /// Copies of every key that's still usable, with their ages and uses
+    pub fn snapshot(&self) -> Vec<PooledKey<KeyInfo>> {
+        self.lock().slots.iter().flatten().cloned().collect()
+    }
+}
+
+impl KeyPool<RSAKeyInfo> {
+    /// Save the pool to an encrypted key store file. Returns how many keys were saved
+    pub fn save_snapshot(&self, path: &Path, passphrase: &str) -> Result<usize, KeyStoreError> {
+        let pooled: Vec<PooledKey<RSAKeyInfo>> = self.snapshot();
+        let mut store: KeyStore = KeyStore::create(passphrase);
+        for (index, pooled_key) in pooled.iter().enumerate() {
+            let uses: String = format!("{}{}", USES_LABEL_PREFIX, pooled_key.uses);
+            store.add_created_at(&index.to_string(), &[SNAPSHOT_LABEL, &uses], &pooled_key.key, pooled_key.created)?;
+        }
+        store.save(path)?;
+        Ok(pooled.len())
+    }
+    /// Refill the pool from a snapshot, skipping keys that expired or were used up in the meantime.
+    /// Returns how many keys were loaded
+    pub fn load_snapshot(&self, path: &Path, passphrase: &str) -> Result<usize, KeyStoreError> {
+        let store: KeyStore = KeyStore::open(path, passphrase)?;
+        let now: SystemTime = SystemTime::now();
+        let mut loaded: usize = 0;
+
+        for metadata in store.metadata() {
+            if !metadata.has_label(SNAPSHOT_LABEL) { continue; }
+            let uses: u32 = metadata.labels.iter()
+                .find_map(|label| label.strip_prefix(USES_LABEL_PREFIX))
+                .and_then(|uses| uses.parse::<u32>().ok())
+                .unwrap_or(0);
+            let fresh: bool = now.duration_since(metadata.created).is_ok_and(|age| age < self.config.max_age);
+            if !fresh || uses >= self.config.max_uses { continue; }
+
+            self.insert(PooledKey { key: store.load(&metadata.id)?, created: metadata.created, uses });
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+}
+
+/// How hard a KeyWorker works
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerConfig {
+    /// Once the pool is full, how often to mix in a new key in place of the oldest one
+    pub interval: Duration,
+    /// The fraction of one core the worker may use, from MIN_CPU_BUDGET to 1. After spending t
+    /// generating a key, the worker idles for t * (1 - budget) / budget
+    pub cpu_budget: f32
+}
+/// The smallest CPU budget a worker takes, where it idles 999 times as long as it works. Any less and
+/// the idle time stops being useful, and for tiny budgets it's too long for a Duration to hold
+pub const MIN_CPU_BUDGET: f32 = 0.001;
+impl WorkerConfig {
+    /// A new key every minute once the pool is full, using half a core
+    pub const DEFAULT: WorkerConfig = WorkerConfig { interval: Duration::from_secs(60), cpu_budget: 0.5 };
+}
+
+// How often a worker waiting on a full pool checks whether it's been stopped
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// The stop flag, with a condvar so a sleeping worker wakes up as soon as it's set
+type StopSignal = Arc<(Mutex<bool>, Condvar)>;
+
+/// A thread generating keys into a KeyPool. Dropping it tells the thread to stop once it's done
+/// with the key it's working on, without waiting for that, since big keys can take minutes
+pub struct KeyWorker {
+    stop: StopSignal,
+    thread: Option<JoinHandle<()>>
+}
+impl KeyWorker {
+    /// Start a worker. The generator is made on the worker thread, since things like ThreadRng can't be sent.
+    /// If the generator gives up and returns None, the worker stops
+    pub fn spawn<KeyInfo, Generator, MakeGenerator>(
+        pool: Arc<KeyPool<KeyInfo>>, config: WorkerConfig, make_generator: MakeGenerator
+    ) -> Result<Self, PoolConfigError>
+    where
+        KeyInfo: Clone + Send + 'static,
+        Generator: FnMut() -> Option<KeyInfo>,
+        MakeGenerator: FnOnce() -> Generator + Send + 'static
+    {
+        // Written so NaN fails too
+        if !(config.cpu_budget >= MIN_CPU_BUDGET && config.cpu_budget <= 1.0) { return Err(PoolConfigError::InvalidCpuBudget(config.cpu_budget)); }
+        let stop: StopSignal = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop: StopSignal = Arc::clone(&stop);
+
+        let thread = thread::spawn(move || {
+            let mut generate = make_generator();
+            loop {
+                let started: Instant = Instant::now();
+                let Some(key) = generate() else { return; };
+                let elapsed: Duration = started.elapsed();
+                if *thread_stop.0.lock().unwrap() { return; }
+                pool.insert_key(key);
+
+                // Keep to the CPU budget
+                let idle: Duration = elapsed.mul_f32((1.0 - config.cpu_budget) / config.cpu_budget);
+                let stopped: bool = *thread_stop.1.wait_timeout_while(
+                    thread_stop.0.lock().unwrap(), idle, |stopped| !*stopped
+                ).unwrap().0;
+                if stopped { return; }
+
+                // Once the pool is full, only refresh it every interval, or sooner if a key is about to
+                // expire or gets used up. Wait in short steps so a stop doesn't go unnoticed for long
+                if pool.is_full() {
+                    let refresh: Duration = config.interval.min(pool.time_until_next_expiry().unwrap_or(config.interval));
+                    // An interval too long for an Instant to hold means waiting until a key is used
+                    let deadline: Option<Instant> = Instant::now().checked_add(refresh.saturating_sub(elapsed));
+                    loop {
+                        let remaining: Duration = deadline.map_or(Duration::MAX, |deadline| deadline.saturating_duration_since(Instant::now()));
+                        if remaining.is_zero() || !pool.wait_while_full(remaining.min(STOP_CHECK_INTERVAL)) { break; }
+                        if *thread_stop.0.lock().unwrap() { return; }
+                    }
+                }
+            }
+        });
+        Ok(Self { stop, thread: Some(thread) })
+    }
+
+    /// Stop the worker and wait for it to finish
+    pub fn join(mut self) {
+        self.signal_stop();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+    fn signal_stop(&self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+    }
+}
+impl Drop for KeyWorker {
+    fn drop(&mut self) {
+        self.signal_stop();
+    }
+}
+
+/// A thread saving a pool of RSA keys to a snapshot file every interval, whenever it's changed,
+/// and once more when it's stopped. The server can be killed without warning, so this is what
+/// keeps the snapshot close to the pool: at worst it's one interval behind
+pub struct SnapshotWriter {
+    stop: StopSignal,
+    thread: Option<JoinHandle<()>>,
+    // The error from the last save that failed, if it hasn't been picked up yet
+    last_error: Arc<Mutex<Option<KeyStoreError>>>
+}
+impl SnapshotWriter {
+    /// Start saving the pool to the snapshot at path, encrypted with the passphrase
+    pub fn spawn(pool: Arc<KeyPool<RSAKeyInfo>>, path: PathBuf, passphrase: &str, interval: Duration) -> Self {
+        let stop: StopSignal = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop: StopSignal = Arc::clone(&stop);
+        let last_error: Arc<Mutex<Option<KeyStoreError>>> = Arc::new(Mutex::new(None));
+        let thread_error: Arc<Mutex<Option<KeyStoreError>>> = Arc::clone(&last_error);
+        let passphrase: Zeroizing<Vec<u8>> = Zeroizing::new(passphrase.as_bytes().to_vec());
+
+        let thread = thread::spawn(move || {
+            // Made from a &str, so it's still valid UTF-8
+            let passphrase: &str = std::str::from_utf8(&passphrase).unwrap();
+            let mut saved: Option<PoolStats> = None;
+            loop {
+                let stopped: bool = *thread_stop.1.wait_timeout_while(
+                    thread_stop.0.lock().unwrap(), interval, |stopped| !*stopped
+                ).unwrap().0;
+
+                // Every change to the pool shows up in its counters, so only save when they've moved
+                let stats: PoolStats = pool.stats();
+                if saved != Some(stats) || stopped {
+                    match pool.save_snapshot(&path, passphrase) {
+                        Ok(_) => saved = Some(stats),
+                        Err(error) => *thread_error.lock().unwrap() = Some(error)
+                    }
+                }
+                if stopped { return; }
+            }
+        });
+        Self { stop, thread: Some(thread), last_error }
+    }
+
+    /// The error from the last save that failed, if there's been one since the last call
+    pub fn take_error(&self) -> Option<KeyStoreError> {
+        self.last_error.lock().unwrap().take()
+    }
+    /// Stop the writer, waiting for its final save
+    pub fn join(mut self) -> Option<KeyStoreError> {
+        self.stop_and_wait();
+        self.take_error()
+    }
+    fn stop_and_wait(&mut self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+impl Drop for SnapshotWriter {
+    fn drop(&mut self) {
+        self.stop_and_wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_worker(cpu_budget: f32) -> Result<KeyWorker, PoolConfigError> {
+        let pool: Arc<KeyPool<u32>> = Arc::new(KeyPool::new(PoolConfig::DEFAULT).unwrap());
+        KeyWorker::spawn(pool, WorkerConfig { cpu_budget, ..WorkerConfig::DEFAULT }, || || None)
+    }
+
+    #[test]
+    fn rejects_invalid_pool_configs() {
+        assert!(matches!(KeyPool::<u32>::new(PoolConfig { capacity: 0, ..PoolConfig::DEFAULT }), Err(PoolConfigError::ZeroCapacity)));
+        assert!(matches!(KeyPool::<u32>::new(PoolConfig { max_uses: 0, ..PoolConfig::DEFAULT }), Err(PoolConfigError::ZeroMaxUses)));
+        assert!(KeyPool::<u32>::new(PoolConfig { capacity: 1, max_uses: 1, ..PoolConfig::DEFAULT }).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_cpu_budgets() {
+        for budget in [0.0, -0.5, 1e-40, MIN_CPU_BUDGET / 2.0, 1.5, f32::INFINITY, f32::NAN] {
+            assert!(matches!(spawn_worker(budget), Err(PoolConfigError::InvalidCpuBudget(_))), "budget {}", budget);
+        }
+        for budget in [MIN_CPU_BUDGET, 0.01, 0.5, 1.0] {
+            spawn_worker(budget).unwrap().join();
+        }
+    }
+
+    #[test]
+    fn waits_forever_for_huge_timeouts() {
+        let pool: Arc<KeyPool<u32>> = Arc::new(KeyPool::new(PoolConfig::DEFAULT).unwrap());
+        let inserter = {
+            let pool: Arc<KeyPool<u32>> = Arc::clone(&pool);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                pool.insert_key(7);
+            })
+        };
+        assert_eq!(pool.get_random_timeout(Duration::MAX), Some(7));
+        inserter.join().unwrap();
+        // And with a key already there, it doesn't wait at all
+        assert_eq!(pool.get_random_timeout(Duration::MAX), Some(7));
+    }
+
+    #[test]
+    fn worker_handles_extreme_configs() {
+        // The smallest budget, and an interval too long to add to an Instant, once the pool is full
+        let pool: Arc<KeyPool<u32>> = Arc::new(KeyPool::new(PoolConfig { capacity: 1, ..PoolConfig::DEFAULT }).unwrap());
+        let config: WorkerConfig = WorkerConfig { interval: Duration::MAX, cpu_budget: MIN_CPU_BUDGET };
+        let worker: KeyWorker = KeyWorker::spawn(Arc::clone(&pool), config, || || Some(7)).unwrap();
+        while !pool.is_full() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread::sleep(Duration::from_millis(50));
+        worker.join();
+    }
+
+    #[test]
+    fn retires_keys_after_max_uses() {
+        let pool: KeyPool<u32> = KeyPool::new(PoolConfig { capacity: 2, max_uses: 2, ..PoolConfig::DEFAULT }).unwrap();
+        assert_eq!(pool.get_random_timeout(Duration::ZERO), None);
+        pool.insert_key(7);
+        assert_eq!(pool.get_random_timeout(Duration::ZERO), Some(7));
+        assert_eq!(pool.get_random_timeout(Duration::ZERO), Some(7));
+        assert_eq!(pool.get_random_timeout(Duration::ZERO), None);
+
+        let stats: PoolStats = pool.stats();
+        assert_eq!((stats.served, stats.used_up, stats.timeouts), (2, 1, 2));
+    }
+}
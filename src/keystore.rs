@@ -0,0 +1,398 @@
+/* An on-disk store of private keys, each encrypted with ChaCha20-Poly1305 under a key derived from a
+    passphrase with PBKDF2-HMAC-SHA256. Every key has an ID, a creation date and usage labels. Those are
+    stored in the clear so the store can be listed without the passphrase, but they're authenticated
+    along with the key, so they can't be changed or moved to another entry without it being noticed.
+
+    File layout (integers are big endian, strings are a u32 length followed by the bytes):
+        magic "RSAKSTR" then the format version 0x01, u32 KDF iterations, 16 byte salt,
+        12 byte nonce and 16 byte tag sealing nothing (to check the passphrase),
+        u32 entry count, then for each entry:
+            string ID, u64 creation time in seconds since the Unix epoch, u32 label count, string labels,
+            12 byte nonce, string sealed PKCS#8 private key */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use crate::aead;
+use crate::hash;
+use crate::keyformat::KeyFormatError;
+use crate::keygen::{ self, RSAKeyInfo };
+use crate::zeroize::{ Zeroize, Zeroizing };
+
+const MAGIC: &[u8; 8] = b"RSAKSTR\x01";
+const SALT_BYTES: usize = 16;
+/// OWASP's 2023 recommendation for PBKDF2-HMAC-SHA256
+pub const DEFAULT_KDF_ITERATIONS: u32 = 600_000;
+/// The fewest KDF iterations a store can use. The count comes from the file, so without limits a
+/// crafted store could make opening it run the KDF for hours, or barely run it at all
+pub const MIN_KDF_ITERATIONS: u32 = 100_000;
+/// The most KDF iterations a store can use
+pub const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+/// Why a store couldn't be read, written or used
+#[derive(Debug)]
+pub enum KeyStoreError {
+    /// Reading or writing the file failed
+    Io(io::Error),
+    /// Not a key store, or a newer version of one
+    BadMagic,
+    /// The file ends early or has data after the last entry
+    Malformed,
+    /// A KDF iteration count outside MIN_KDF_ITERATIONS..=MAX_KDF_ITERATIONS
+    BadKdfIterations(u32),
+    /// The passphrase doesn't decrypt the store's check value
+    WrongPassphrase,
+    /// An entry failed to decrypt even though the passphrase is right, so it was tampered with
+    Corrupted(String),
+    /// A key with this ID is already in the store
+    DuplicateId(String),
+    /// No key in the store has this ID
+    UnknownId(String),
+    /// Nothing in the store has the usage label we asked for
+    NoKeysWithLabel(String),
+    /// A decrypted key didn't parse or validate
+    Key(KeyFormatError),
+    /// The stored keys are a size we can't generate more of
+    KeyGen(keygen::Error)
+}
+impl From<io::Error> for KeyStoreError {
+    fn from(error: io::Error) -> Self {
+        KeyStoreError::Io(error)
+    }
+}
+impl From<KeyFormatError> for KeyStoreError {
+    fn from(error: KeyFormatError) -> Self {
+        KeyStoreError::Key(error)
+    }
+}
+impl From<keygen::Error> for KeyStoreError {
+    fn from(error: keygen::Error) -> Self {
+        KeyStoreError::KeyGen(error)
+    }
+}
+
+/// What we know about a key without decrypting it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMetadata {
+    /// The name the key is stored under, unique in its store
+    pub id: String,
+    /// When the key was made, to the second
+    pub created: SystemTime,
+    /// What the key is for, like "server" or "pool"
+    pub labels: Vec<String>
+}
+impl KeyMetadata {
+    /// Whether one of the labels is this one
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|other| other == label)
+    }
+
+    fn write(&self, output: &mut Vec<u8>) {
+        write_string(output, self.id.as_bytes());
+        let seconds: u64 = self.created.duration_since(UNIX_EPOCH).map(|age| age.as_secs()).unwrap_or(0);
+        output.extend_from_slice(&seconds.to_be_bytes());
+        output.extend_from_slice(&(self.labels.len() as u32).to_be_bytes());
+        for label in &self.labels {
+            write_string(output, label.as_bytes());
+        }
+    }
+    fn read(reader: &mut Reader) -> Result<Self, KeyStoreError> {
+        let id: String = reader.read_utf8()?;
+        // A corrupt file can have any number here, including ones too big for a SystemTime
+        let created: SystemTime = UNIX_EPOCH.checked_add(Duration::from_secs(reader.read_u64()?)).ok_or(KeyStoreError::Malformed)?;
+        let label_count: u32 = reader.read_u32()?;
+        let mut labels: Vec<String> = Vec::new();
+        for _ in 0..label_count {
+            labels.push(reader.read_utf8()?);
+        }
+        Ok(Self { id, created, labels })
+    }
+}
+
+struct Entry {
+    metadata: KeyMetadata,
+    nonce: [u8; aead::NONCE_BYTES],
+    sealed: Vec<u8>
+}
+
+/// Private keys encrypted under one passphrase, with their IDs and labels in the clear
+pub struct KeyStore {
+    iterations: u32,
+    salt: [u8; SALT_BYTES],
+    check_nonce: [u8; aead::NONCE_BYTES],
+    check_tag: Vec<u8>,
+    entries: Vec<Entry>,
+    // Derived from the passphrase when the store is created or opened
+    key: [u8; aead::KEY_BYTES]
+}
+
+fn check_kdf_iterations(iterations: u32) -> Result<(), KeyStoreError> {
+    if !(MIN_KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&iterations) {
+        return Err(KeyStoreError::BadKdfIterations(iterations));
+    }
+    Ok(())
+}
+
+fn write_string(output: &mut Vec<u8>, contents: &[u8]) {
+    output.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+    output.extend_from_slice(contents);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], KeyStoreError> {
+        if self.data.len() - self.position < count { return Err(KeyStoreError::Malformed); }
+        let bytes: &'a [u8] = &self.data[self.position..(self.position + count)];
+        self.position += count;
+        Ok(bytes)
+    }
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], KeyStoreError> {
+        let mut array: [u8; N] = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+    fn read_u32(&mut self) -> Result<u32, KeyStoreError> {
+        Ok(u32::from_be_bytes(self.read_array::<4>()?))
+    }
+    fn read_u64(&mut self) -> Result<u64, KeyStoreError> {
+        Ok(u64::from_be_bytes(self.read_array::<8>()?))
+    }
+    fn read_string(&mut self) -> Result<&'a [u8], KeyStoreError> {
+        let length: usize = self.read_u32()? as usize;
+        self.read_bytes(length)
+    }
+    fn read_utf8(&mut self) -> Result<String, KeyStoreError> {
+        String::from_utf8(self.read_string()?.to_vec()).map_err(|_| KeyStoreError::Malformed)
+    }
+}
+
+impl Drop for KeyStore {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+impl KeyStore {
+    fn derive_key(passphrase: &str, salt: &[u8; SALT_BYTES], iterations: u32) -> [u8; aead::KEY_BYTES] {
+        let mut key: [u8; aead::KEY_BYTES] = [0; aead::KEY_BYTES];
+        hash::pbkdf2_sha256(passphrase.as_bytes(), salt, iterations, &mut key);
+        key
+    }
+    // The start of the file, which the passphrase check and every entry are bound to
+    fn header(&self) -> Vec<u8> {
+        let mut header: Vec<u8> = MAGIC.to_vec();
+        header.extend_from_slice(&self.iterations.to_be_bytes());
+        header.extend_from_slice(&self.salt);
+        header
+    }
+    fn entry_associated_data(&self, metadata: &KeyMetadata) -> Vec<u8> {
+        let mut associated_data: Vec<u8> = self.header();
+        metadata.write(&mut associated_data);
+        associated_data
+    }
+
+    /// A new, empty store
+    pub fn create(passphrase: &str) -> Self {
+        Self::new_with_iterations(passphrase, DEFAULT_KDF_ITERATIONS)
+    }
+    /// A new, empty store with a different KDF iteration count, which has to be
+    /// between MIN_KDF_ITERATIONS and MAX_KDF_ITERATIONS
+    pub fn create_with_iterations(passphrase: &str, iterations: u32) -> Result<Self, KeyStoreError> {
+        check_kdf_iterations(iterations)?;
+        Ok(Self::new_with_iterations(passphrase, iterations))
+    }
+    fn new_with_iterations(passphrase: &str, iterations: u32) -> Self {
+        let salt: [u8; SALT_BYTES] = rand::random();
+        let mut store = Self {
+            iterations,
+            salt,
+            check_nonce: rand::random(),
+            check_tag: Vec::new(),
+            entries: Vec::new(),
+            key: Self::derive_key(passphrase, &salt, iterations)
+        };
+        store.check_tag = aead::seal(&store.key, &store.check_nonce, &store.header(), &[]);
+        store
+    }
+
+    // Parse the file without the passphrase, so only the metadata is usable
+    fn parse(data: &[u8]) -> Result<Self, KeyStoreError> {
+        let mut reader: Reader = Reader { data, position: 0 };
+        if reader.read_bytes(MAGIC.len())? != MAGIC { return Err(KeyStoreError::BadMagic); }
+        let iterations: u32 = reader.read_u32()?;
+        check_kdf_iterations(iterations)?;
+        let salt: [u8; SALT_BYTES] = reader.read_array()?;
+        let check_nonce: [u8; aead::NONCE_BYTES] = reader.read_array()?;
+        let check_tag: Vec<u8> = reader.read_array::<{ aead::TAG_BYTES }>()?.to_vec();
+
+        let entry_count: u32 = reader.read_u32()?;
+        let mut entries: Vec<Entry> = Vec::new();
+        for _ in 0..entry_count {
+            let metadata: KeyMetadata = KeyMetadata::read(&mut reader)?;
+            let nonce: [u8; aead::NONCE_BYTES] = reader.read_array()?;
+            let sealed: Vec<u8> = reader.read_string()?.to_vec();
+            entries.push(Entry { metadata, nonce, sealed });
+        }
+        if reader.position != data.len() { return Err(KeyStoreError::Malformed); }
+
+        Ok(Self { iterations, salt, check_nonce, check_tag, entries, key: [0; aead::KEY_BYTES] })
+    }
+
+    /// Open a store file, checking the passphrase
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, KeyStoreError> {
+        let mut store: KeyStore = Self::parse(&fs::read(path)?)?;
+        store.key = Self::derive_key(passphrase, &store.salt, store.iterations);
+        if aead::open(&store.key, &store.check_nonce, &store.header(), &store.check_tag).is_err() {
+            return Err(KeyStoreError::WrongPassphrase);
+        }
+        Ok(store)
+    }
+    /// List the keys in a store file. This doesn't need the passphrase
+    pub fn list(path: &Path) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+        let mut store: KeyStore = Self::parse(&fs::read(path)?)?;
+        Ok(std::mem::take(&mut store.entries).into_iter().map(|entry| entry.metadata).collect())
+    }
+
+    /// Write the store to a file, replacing whatever was there
+    pub fn save(&self, path: &Path) -> Result<(), KeyStoreError> {
+        let mut output: Vec<u8> = self.header();
+        output.extend_from_slice(&self.check_nonce);
+        output.extend_from_slice(&self.check_tag);
+        output.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            entry.metadata.write(&mut output);
+            output.extend_from_slice(&entry.nonce);
+            write_string(&mut output, &entry.sealed);
+        }
+
+        // Write to a temporary file first, so a crash partway through can't wipe out the old store
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, &output)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// What's known about each key, in the order they were added
+    pub fn metadata(&self) -> impl Iterator<Item = &KeyMetadata> {
+        self.entries.iter().map(|entry| &entry.metadata)
+    }
+
+    /// Encrypt and add a key made just now
+    pub fn add(&mut self, id: &str, labels: &[&str], keys: &RSAKeyInfo) -> Result<(), KeyStoreError> {
+        self.add_created_at(id, labels, keys, SystemTime::now())
+    }
+    /// Add a key that was made earlier, keeping its creation time (to the second)
+    pub fn add_created_at(&mut self, id: &str, labels: &[&str], keys: &RSAKeyInfo, created: SystemTime) -> Result<(), KeyStoreError> {
+        if self.entries.iter().any(|entry| entry.metadata.id == id) { return Err(KeyStoreError::DuplicateId(id.to_string())); }
+
+        let metadata = KeyMetadata {
+            id: id.to_string(),
+            created,
+            labels: labels.iter().map(|label| label.to_string()).collect()
+        };
+        // Every entry gets its own random nonce. With a 96-bit nonce that's safe for far more
+        // keys than a store will ever hold
+        let nonce: [u8; aead::NONCE_BYTES] = rand::random();
+        let der: Zeroizing<Vec<u8>> = Zeroizing::new(keys.to_pkcs8_der()?);
+        let sealed: Vec<u8> = aead::seal(&self.key, &nonce, &self.entry_associated_data(&metadata), &der);
+        self.entries.push(Entry { metadata, nonce, sealed });
+        Ok(())
+    }
+    /// Remove a key from the store. It's only gone from the file once the store is saved
+    pub fn remove(&mut self, id: &str) -> Result<(), KeyStoreError> {
+        let index: usize = self.entries.iter().position(|entry| entry.metadata.id == id)
+            .ok_or_else(|| KeyStoreError::UnknownId(id.to_string()))?;
+        self.entries.remove(index);
+        Ok(())
+    }
+
+    fn decrypt(&self, entry: &Entry) -> Result<RSAKeyInfo, KeyStoreError> {
+        let der: Zeroizing<Vec<u8>> = Zeroizing::new(
+            aead::open(&self.key, &entry.nonce, &self.entry_associated_data(&entry.metadata), &entry.sealed)
+                .map_err(|_| KeyStoreError::Corrupted(entry.metadata.id.clone()))?
+        );
+        // This validates the key, same as any other import
+        Ok(RSAKeyInfo::from_pkcs8_der(&der)?)
+    }
+    /// Decrypt the key with this ID
+    pub fn load(&self, id: &str) -> Result<RSAKeyInfo, KeyStoreError> {
+        let entry: &Entry = self.entries.iter().find(|entry| entry.metadata.id == id)
+            .ok_or_else(|| KeyStoreError::UnknownId(id.to_string()))?;
+        self.decrypt(entry)
+    }
+    /// Every key with the given usage label
+    pub fn load_with_label(&self, label: &str) -> Result<Vec<RSAKeyInfo>, KeyStoreError> {
+        self.entries.iter()
+            .filter(|entry| entry.metadata.has_label(label))
+            .map(|entry| self.decrypt(entry))
+            .collect()
+    }
+    /// Decrypt every key in the store
+    pub fn load_all(&self) -> Result<Vec<RSAKeyInfo>, KeyStoreError> {
+        self.entries.iter().map(|entry| self.decrypt(entry)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keygen::NumberHandler;
+
+    // The start of a store file with this iteration count, which is as far as parse gets before checking it
+    fn header_with_iterations(iterations: u32) -> Vec<u8> {
+        let mut data: Vec<u8> = MAGIC.to_vec();
+        data.extend_from_slice(&iterations.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn rejects_iteration_counts_out_of_range() {
+        for iterations in [0, 1, MIN_KDF_ITERATIONS - 1, MAX_KDF_ITERATIONS + 1, u32::MAX] {
+            assert!(matches!(KeyStore::parse(&header_with_iterations(iterations)), Err(KeyStoreError::BadKdfIterations(count)) if count == iterations));
+            assert!(matches!(KeyStore::create_with_iterations("passphrase", iterations), Err(KeyStoreError::BadKdfIterations(_))));
+        }
+        // In range, so parsing gets as far as the missing salt
+        assert!(matches!(KeyStore::parse(&header_with_iterations(MIN_KDF_ITERATIONS)), Err(KeyStoreError::Malformed)));
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let keys: RSAKeyInfo = NumberHandler::new(16).unwrap().get_rsa_keys().unwrap();
+        let path = std::env::temp_dir().join(format!("keystore-test-{}.store", std::process::id()));
+
+        let mut store: KeyStore = KeyStore::create_with_iterations("passphrase", MIN_KDF_ITERATIONS).unwrap();
+        store.add("first", &["server"], &keys).unwrap();
+        store.save(&path).unwrap();
+
+        let opened: KeyStore = KeyStore::open(&path, "passphrase").unwrap();
+        let loaded: RSAKeyInfo = opened.load("first").unwrap();
+        assert!(loaded.shared == keys.shared && loaded.private == keys.private);
+        assert!(matches!(KeyStore::open(&path, "wrong passphrase"), Err(KeyStoreError::WrongPassphrase)));
+        assert_eq!(KeyStore::list(&path).unwrap()[0].labels, vec!["server".to_string()]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_creation_times_out_of_range() {
+        let keys: RSAKeyInfo = NumberHandler::new(16).unwrap().get_rsa_keys().unwrap();
+        let path = std::env::temp_dir().join(format!("keystore-time-test-{}.store", std::process::id()));
+        let seconds: u64 = 1_234_567_890;
+
+        let mut store: KeyStore = KeyStore::create_with_iterations("passphrase", MIN_KDF_ITERATIONS).unwrap();
+        store.add_created_at("first", &[], &keys, UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+        store.save(&path).unwrap();
+        assert_eq!(KeyStore::list(&path).unwrap()[0].created, UNIX_EPOCH + Duration::from_secs(seconds));
+
+        // Listing doesn't need the passphrase, so it's the first thing a corrupt file gets to
+        let mut data: Vec<u8> = fs::read(&path).unwrap();
+        let position: usize = data.windows(8).position(|window| window == seconds.to_be_bytes()).unwrap();
+        data[position..(position + 8)].copy_from_slice(&u64::MAX.to_be_bytes());
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(KeyStore::list(&path), Err(KeyStoreError::Malformed)));
+        fs::remove_file(&path).unwrap();
+    }
+}
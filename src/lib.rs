@@ -0,0 +1,78 @@
+//! A network transport that authenticates with RSA keys it generates itself.
+//!
+//! The crypto core builds without std, so key generation and hashing can run on embedded
+//! gateways. Turn off default features to get it, and `alloc` to add the prime sieve:
+//! - [`hash`]: SHA-256, HMAC-SHA256 and PBKDF2
+//! - [`keygen`]: primes, RSA keys and the modular arithmetic behind them, with randomness
+//!   from any [`rand::CryptoRng`] the caller supplies
+//! - [`primes`], [`multiply`], [`modular`] and [`zeroize`], which keygen is built on
+//!
+//! With std (the default) there's everything the server needs as well: key formats, the
+//! encrypted key store, the key pool, auditing, the socket layer and the `Server` itself.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![warn(missing_docs)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+/// SHA-256, HMAC-SHA256 and PBKDF2-HMAC-SHA256
+pub mod hash;
+/// Primes that fit in a machine word: the small prime tables, a segmented sieve and u64 primality
+pub mod primes;
+/// Multiplying big integers limb by limb, with schoolbook and Karatsuba multiplication
+pub mod multiply;
+/// Barrett and Montgomery reduction, for many products by the same modulus
+pub mod modular;
+/// Random primes, RSA keys and their validation, and the modular arithmetic they need
+pub mod keygen;
+/// Wiping secrets from memory in a way the compiler can't optimise out
+pub mod zeroize;
+
+/// ChaCha20-Poly1305 authenticated encryption (RFC 8439)
+#[cfg(feature = "std")]
+pub mod aead;
+/// The subset of ASN.1 DER that RSA keys need
+#[cfg(feature = "std")]
+pub mod asn1;
+/// Checking RSA moduli for the weaknesses that let them be factored
+#[cfg(feature = "std")]
+pub mod audit;
+/// Standard base64 (RFC 4648)
+#[cfg(feature = "std")]
+pub mod base64;
+/// Public key fingerprints as hex, base64, BIP-39 words and randomart
+#[cfg(feature = "std")]
+pub mod fingerprint;
+/// RSA keys as PKCS#1, PKCS#8 and SubjectPublicKeyInfo, in DER or PEM
+#[cfg(feature = "std")]
+pub mod keyformat;
+/// A pool of pre-generated keys, and the worker thread that fills it
+#[cfg(feature = "std")]
+pub mod keypool;
+/// An on-disk store of passphrase-encrypted private keys
+#[cfg(feature = "std")]
+pub mod keystore;
+/// OpenSSH public and private key formats, and known_hosts lines
+#[cfg(feature = "std")]
+pub mod openssh;
+/// PEM armor (RFC 7468)
+#[cfg(feature = "std")]
+pub mod pem;
+/// Timing key generation and counting where the prime search spends its candidates
+#[cfg(feature = "std")]
+pub mod profile;
+/// The transport server
+#[cfg(feature = "std")]
+pub mod server;
+/// Raw sockets for the transport
+#[cfg(feature = "std")]
+pub mod socket;
+
+pub use keygen::{ Key, NumberHandler, RSAKeyInfo, RSAPublicKey };
+#[cfg(feature = "std")]
+pub use keystore::KeyStore;
+#[cfg(feature = "std")]
+pub use openssh::{ SshPrivateKey, SshPublicKey };
+#[cfg(feature = "std")]
+pub use server::Server;
@@ -1,100 +1,47 @@
-use std::env;
-use rand::prelude::*;
-
-mod primes;
-mod hash;
-use hash::{ sha256 };
-mod keygen;
-use keygen::{ Key, RSAKeyInfo, NumberHandler, bigmod };
-
-mod socket;
-use crate::socket::WinSock;
-
-trait Default {
-    const DEFAULT: Self;
-}
-impl Default for RSAKeyInfo {
-    const DEFAULT: RSAKeyInfo = RSAKeyInfo { public: Key::ZERO, private: Key::ZERO, shared: Key::ZERO };
-}
+/* The command line tool: runs the server, and generates, stores, fingerprints and audits keys.
+    Everything it does is in the library, so this only parses arguments and prints results. */
 
-/* Keys take a while to generate, so the server will store a list of keys from which to choose randomly.
-    It will keep generating new keys in the background and add the key info to the list of possible keys.
-    We also can't store an unlimited number of keys -- they're way too big. So, after a while, we'll overwrite
-    the oldest keys. */
-struct KeysContainer<KeyInfo: Default, const N: usize> {
-    keys: [KeyInfo; N],
-    current_size: usize,
-    insert_index: usize
+use std::env;
+use std::io::{ self, BufRead, Write };
+use std::path::Path;
+
+use custom_user_network_transport::audit::{ self, AuditLimits };
+use custom_user_network_transport::keygen::{ Key, NumberHandler, RSAKeyInfo, RSAPublicKey };
+use custom_user_network_transport::keystore::KeyStore;
+use custom_user_network_transport::openssh::{ SshPrivateKey, SshPublicKey };
+use custom_user_network_transport::profile::{ self, KeyGenProfile };
+use custom_user_network_transport::server::{ DEFAULT_KEY_BYTE_SIZE, DEFAULT_SNAPSHOT_INTERVAL, SERVER_KEY_LABEL, Server };
+use custom_user_network_transport::socket;
+#[cfg(target_os = "windows")]
+use custom_user_network_transport::socket::WinSock;
+
+// Take the key store passphrase from the environment if it's there, otherwise ask for it
+fn read_passphrase() -> String {
+    if let Ok(passphrase) = env::var("KEY_STORE_PASSPHRASE") { return passphrase; }
+
+    eprint!("Key store passphrase: ");
+    io::stderr().flush().unwrap();
+    let mut passphrase: String = String::new();
+    io::stdin().lock().read_line(&mut passphrase).expect("Failed to read passphrase");
+    passphrase.trim_end_matches(['\r', '\n']).to_string()
 }
-impl<KeyInfo: Default + Copy, const N: usize> KeysContainer<KeyInfo, { N }> {
-    fn new() -> Self {
-        Self{
-            keys: [KeyInfo::DEFAULT; N],
-            current_size: 0,
-            insert_index: 0 }
-    }
-    fn insert_key(&mut self, key: &KeyInfo) {
-        self.keys[self.insert_index] = *key;
-        self.insert_index = self.insert_index + 1;
-        // Start overwriting if we're at max capacity
-        if self.current_size < N - 1 {
-            self.current_size = self.current_size + 1;
-            self.insert_index = self.insert_index % N;
-        }
+fn open_or_create_key_store(path: &Path, passphrase: &str) -> KeyStore {
+    if path.exists() {
+        KeyStore::open(path, passphrase).expect("Failed to open key store")
     }
-    fn get_random(&self, rng: &mut ThreadRng) -> &KeyInfo {
-        // Make sure the list isn't empty. If it is, we can't sample from it
-        assert!(self.current_size > 0);
-        &self.keys[rng.random_range(0usize..self.current_size)]
+    else {
+        KeyStore::create(passphrase)
     }
 }
 
-pub struct Server<const MAX_KEYS: usize = 10> {
-    handler: NumberHandler,
-    rsa_keys: KeysContainer<RSAKeyInfo, { MAX_KEYS }>
-}
-impl<const MAX_KEYS: usize> Server<{ MAX_KEYS }> {
-    // Key size in bytes
-    pub fn new(key_byte_size: usize) -> Self {
-        // A key can be at max one byte less than half of the max capacity
-        // Otherwise, overflow errors will occur
-        assert!(key_byte_size < (Key::BYTES >> 1).try_into().unwrap());
-        Self{ rsa_keys: KeysContainer::<RSAKeyInfo, { MAX_KEYS }>::new(), handler: NumberHandler::new(key_byte_size) }
-    }
-
-    pub fn receive(_request: &Vec<u8>, _response: &mut Vec<u8>) -> bool {
-        true
-    }
-
-    fn get_rsa_keys(&mut self, iterations: u8) -> RSAKeyInfo {
-        let prime_a: Key = self.handler.get_random_prime(iterations);
-        let prime_b: Key = self.handler.get_different_random_prime(iterations, prime_a);
-
-        let shared: Key = prime_a * prime_b;
-        // Compute Euler's totient funtion for the shared
-        let max_range = (prime_a - Key::ONE) * (prime_b - Key::ONE);
-        // Generate random number 1 < N < phi(shared) that is coprime with phi(shared)
-        let private = self.handler.gen_random_coprime_number_in_range(Key::ONE, max_range, max_range);
-        let public = keygen::get_modular_inverse(private, max_range);
-
-        RSAKeyInfo{ private, public, shared }
-    }
-    // fn hash_dhke(&self, dhke: DHKEKeyInfo) -> [u64; 4] {}
-    // fn get_dhke_keys(&mut self, iterations: u8) -> DHKEKeyInfo {
-    //     let shared_base: Key = self.handler.get_random_prime(iterations);
-    //     let shared_mod: Key = self.handler.gen_random_coprime(shared_base);
-    // }
-    fn handle_client(&mut self) -> bool {
-        let keys: RSAKeyInfo = self.get_rsa_keys(64);
-        println!("{}", keys);
-
-        let payload = Key::NINE;
-        let encrypted = bigmod(payload, keys.private, keys.shared);
-        let decrypted = bigmod(encrypted, keys.public, keys.shared);
-        println!("{}", decrypted);
-
-        true
-    }
+// Get the public key out of any key file we can read: OpenSSH public or private keys,
+// or PEM public or private keys
+fn read_any_public_key(text: &str) -> Option<SshPublicKey> {
+    if let Ok((key, _comment)) = SshPublicKey::from_openssh_line(text.trim()) { return Some(key); }
+    if let Ok((key, _comment)) = SshPrivateKey::from_openssh_private(text) { return Some(key.public_key()); }
+    if let Ok(keys) = RSAKeyInfo::from_pem(text) { return Some(SshPublicKey::Rsa(Box::new(keys.public_key()))); }
+    if let Ok(key) = RSAPublicKey::from_pem(text) { return Some(SshPublicKey::Rsa(Box::new(key))); }
+    None
 }
 
 fn main() {
@@ -109,20 +56,161 @@ fn main() {
     socket::initialize_sockets();
 
     if args.len() == 1 || args[1] == "server" {
-        let server = socket::create_server_socket();
-        socket::server_listen(server);
+        // server [key store]
+        // If KEY_POOL_SNAPSHOT names a file, the key pool is loaded from it on startup, then saved back
+        // to it while the server runs and once more on shutdown, encrypted with the key store passphrase
+        let snapshot: Option<String> = env::var("KEY_POOL_SNAPSHOT").ok();
+        let passphrase: Option<String> = (args.len() > 2 || snapshot.is_some()).then(read_passphrase);
+
+        let mut rsa_server: Option<Server> = None;
+        if args.len() > 2 {
+            let store: KeyStore = KeyStore::open(Path::new(&args[2]), passphrase.as_deref().unwrap()).expect("Failed to open key store");
+            rsa_server = Some(Server::from_key_store(&store, SERVER_KEY_LABEL).expect("Failed to load server keys"));
+            println!("SERVER: Loaded server keys from {}", args[2]);
+        }
+        if let Some(snapshot) = &snapshot {
+            let rsa_server: &mut Server = rsa_server.get_or_insert_with(|| Server::new(DEFAULT_KEY_BYTE_SIZE).expect("Failed to start key pool"));
+            let loaded: usize = rsa_server.start_pool_snapshots(Path::new(snapshot), passphrase.as_deref().unwrap(), DEFAULT_SNAPSHOT_INTERVAL)
+                .expect("Failed to load key pool snapshot");
+            println!("SERVER: Loaded {} pooled keys from {}, saving them back every {:?}", loaded, snapshot, DEFAULT_SNAPSHOT_INTERVAL);
+        }
+
+        // The raw socket server is only written for Winsock so far
+        #[cfg(target_os = "windows")]
+        {
+            let server = socket::create_server_socket();
+            socket::server_listen(server);
+            socket::close_socket(server);
+            socket::clean_up();
+        }
+        #[cfg(not(target_os = "windows"))]
+        eprintln!("SERVER: Sockets are only implemented on Windows so far");
 
-        socket::close_socket(server);
-        socket::clean_up();
+        if let Some(error) = rsa_server.as_mut().and_then(Server::stop_pool_snapshots) {
+            eprintln!("SERVER: Failed to save key pool snapshot: {:?}", error);
+        }
+    }
+    else if args[1] == "keygen" && args.len() > 2 && args[2] == "bench" {
+        // keygen bench [keys per size] [modulus sizes in bits...]
+        // Times generating keys at each size (1024, 2048, 3072 and 4080 bits by default), and counts
+        // the candidates the prime search went through and how fast the keys' modexps are
+        let keys: usize = args.get(3).map(|keys| keys.parse::<usize>().unwrap()).unwrap_or(10);
+        let sizes: Vec<u32> = if args.len() > 4 {
+            args[4..].iter().map(|bits| bits.parse::<u32>().unwrap()).collect()
+        }
+        else {
+            profile::DEFAULT_MODULUS_BITS.to_vec()
+        };
+        for bits in sizes {
+            let result: KeyGenProfile = profile::profile_rsa_keys(bits, keys).expect("Failed to generate keys");
+            println!("{}", result);
+        }
+    }
+    else if args[1] == "keygen" {
+        // keygen <prime size in bytes> <output path>
+        // Writes the private key as PKCS#8 to the path, and the public key as SubjectPublicKeyInfo next to it
+        if args.len() < 4 {
+            panic!("Keygen expects a key size in bytes and an output path");
+        }
+        let key_byte_size: usize = args[2].parse::<usize>().unwrap();
+        let keys: RSAKeyInfo = NumberHandler::new(key_byte_size).and_then(|mut handler| handler.get_rsa_keys()).expect("Failed to generate keys");
+
+        std::fs::write(&args[3], keys.to_pkcs8_pem().expect("Failed to encode private key")).expect("Failed to write private key");
+        std::fs::write(format!("{}.pub", args[3]), keys.public_key().to_spki_pem()).expect("Failed to write public key");
+    }
+    else if args[1] == "keystore" {
+        // keystore list <store>
+        // keystore generate <store> <id> <prime size in bytes> [labels...]
+        // keystore import <store> <id> <PEM private key> [labels...]
+        // keystore remove <store> <id>
+        if args.len() < 4 {
+            panic!("Keystore expects a command and a key store path");
+        }
+        let path: &Path = Path::new(&args[3]);
+
+        if args[2] == "list" {
+            for metadata in KeyStore::list(path).expect("Failed to read key store") {
+                let created: u64 = metadata.created.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+                println!("{}\tcreated {}\t[{}]", metadata.id, created, metadata.labels.join(", "));
+            }
+            return;
+        }
+        if args.len() < 5 {
+            panic!("Keystore {} expects a key ID", args[2]);
+        }
+        let id: &str = &args[4];
+        let mut store: KeyStore = open_or_create_key_store(path, &read_passphrase());
+
+        if args[2] == "remove" {
+            store.remove(id).expect("Failed to remove key");
+        }
+        else {
+            if args.len() < 6 {
+                panic!("Keystore {} expects a key ID and a key", args[2]);
+            }
+            let labels: Vec<&str> = args[6..].iter().map(|label| label.as_str()).collect();
+            let keys: RSAKeyInfo = match args[2].as_str() {
+                "generate" => NumberHandler::new(args[5].parse::<usize>().unwrap())
+                    .and_then(|mut handler| handler.get_rsa_keys())
+                    .expect("Failed to generate keys"),
+                "import" => {
+                    let text: String = std::fs::read_to_string(&args[5]).expect("Failed to read key file");
+                    RSAKeyInfo::from_pem(&text).expect("Failed to read key")
+                },
+                command => panic!("Unknown keystore command {}", command)
+            };
+            store.add(id, &labels, &keys).expect("Failed to add key");
+        }
+        store.save(path).expect("Failed to save key store");
+    }
+    else if args[1] == "fingerprint" {
+        // fingerprint <key file>
+        // Prints the fingerprint of the public key in every format, for checking a key out of band
+        if args.len() < 3 {
+            panic!("Fingerprint expects a key file");
+        }
+        let text: String = std::fs::read_to_string(&args[2]).expect("Failed to read key file");
+        let key: SshPublicKey = read_any_public_key(&text).expect("Not a key file we can read");
+        let fingerprint = key.fingerprint();
+
+        println!("{}", fingerprint.to_base64());
+        println!("{}", fingerprint.to_hex());
+        println!("{}", fingerprint.to_words().join(" "));
+        println!("{}", key.randomart());
+    }
+    else if args[1] == "audit" {
+        // audit <PEM public key files...>
+        // Tries to factor each key's modulus, and checks whether any of them share a prime
+        if args.len() < 3 {
+            panic!("Audit expects at least one key file");
+        }
+        let paths: &[String] = &args[2..];
+        let moduli: Vec<Key> = paths.iter().map(|path| {
+            let text: String = std::fs::read_to_string(path).expect("Failed to read key file");
+            RSAPublicKey::from_pem_unvalidated(&text).expect("Not a PEM public key").shared
+        }).collect();
+
+        for (index, (path, result)) in paths.iter().zip(audit::audit_moduli(&moduli, &AuditLimits::DEFAULT)).enumerate() {
+            match result {
+                Ok(None) => println!("{}\t{}\tno weakness found", index, path),
+                Ok(Some(weakness)) => println!("{}\t{}\tWEAK: {}", index, path, weakness),
+                Err(error) => println!("{}\t{}\tnot audited: {}", index, path, error)
+            }
+        }
     }
     else if args[1] == "queue" {
         if args.len() == 2 {
             panic!("Queue expects a socket index argument");
         }
-        let client = socket::create_client_socket();
-        socket::queue_server(
-            client,
-            WinSock::SOCKET(args[2].parse::<usize>().unwrap())
-        );
+        #[cfg(target_os = "windows")]
+        {
+            let client = socket::create_client_socket();
+            socket::queue_server(
+                client,
+                WinSock::SOCKET(args[2].parse::<usize>().unwrap())
+            );
+        }
+        #[cfg(not(target_os = "windows"))]
+        panic!("Queue's sockets are only implemented on Windows");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,280 @@
+/* Reducing products modulo a fixed modulus without dividing by it every time.
+    Both contexts pay for one wide division up front, then reduce with multiplications only.
+    - Barrett works on ordinary residues and any modulus, and costs two extra multiplications per
+      reduction. Good when a modulus is only used for a handful of products, or is even.
+    - Montgomery keeps residues multiplied by R = 2^(64 k), and reduces with one pass over the
+      limbs that's cheaper than Barrett's two multiplications, but only for odd moduli. Getting
+      in and out of that form costs two reductions, so it pays off over longer runs like modexp.
+    ModularContext picks between them from the modulus and how many products it's going to do. */
+
+use crate::keygen::{ Error, Key, MAX_MODULUS_BITS, WideKey, widen, narrow, widening_mul, widening_square };
+use crate::multiply;
+
+/// How many modular multiplications it takes for Montgomery's conversions in and out to pay off.
+/// Tuned with the reduce group in benches/multiply.rs, where a single product is fastest with a plain
+/// division, and Montgomery overtakes Barrett at about four
+pub const MONTGOMERY_MIN_OPERATIONS: usize = 4;
+
+fn check_modulus(modulus: Key) -> Result<(), Error> {
+    if modulus <= Key::ZERO || modulus.bits() > MAX_MODULUS_BITS { return Err(Error::InvalidModulus); }
+    Ok(())
+}
+
+/// Barrett reduction (Handbook of Applied Cryptography 14.42, with bits instead of limbs).
+/// With n = the modulus' bit length and mu = floor(4^n / m), q = ((x >> (n - 1)) * mu) >> (n + 1)
+/// is at most two less than floor(x / m), for any x < 4^n
+#[derive(Clone, Copy)]
+pub struct BarrettContext {
+    modulus: Key,
+    bits: u32,
+    mu: Key
+}
+impl BarrettContext {
+    /// A context for any modulus bigmod can work with
+    pub fn new(modulus: Key) -> Result<Self, Error> {
+        check_modulus(modulus)?;
+        Ok(Self::new_unchecked(modulus))
+    }
+    pub(crate) fn new_unchecked(modulus: Key) -> Self {
+        let bits: u32 = modulus.bits();
+        // At most n + 1 bits, so it fits in a Key
+        let mu: Key = narrow((WideKey::ONE << (2 * bits)) / widen(modulus));
+        Self { modulus, bits, mu }
+    }
+    /// The modulus the context reduces by
+    pub fn modulus(&self) -> Key {
+        self.modulus
+    }
+
+    /// x mod m, for x < 4^n, which covers any product of two residues
+    pub fn reduce(&self, x: WideKey) -> Key {
+        let estimate: Key = narrow(x >> (self.bits - 1));
+        let quotient: Key = narrow(widening_mul(estimate, self.mu) >> (self.bits + 1));
+        let wide_modulus: WideKey = widen(self.modulus);
+        let mut remainder: WideKey = x - widening_mul(quotient, self.modulus);
+        while remainder >= wide_modulus {
+            remainder -= wide_modulus;
+        }
+        narrow(remainder)
+    }
+    /// ab mod m, for residues a and b
+    pub fn mul_mod(&self, a: Key, b: Key) -> Key {
+        self.reduce(widening_mul(a, b))
+    }
+    /// a^2 mod m, for a residue a
+    pub fn square_mod(&self, a: Key) -> Key {
+        self.reduce(widening_square(a))
+    }
+    /// base^exponent, for 0 <= base < m and exponent >= 0
+    pub fn pow(&self, base: Key, exponent: Key) -> Key {
+        let mut result: Key = Key::ONE % self.modulus;
+        for bit in (0..exponent.bits()).rev() {
+            result = self.square_mod(result);
+            if exponent.bit(bit) { result = self.mul_mod(result, base); }
+        }
+        result
+    }
+}
+
+/// Montgomery multiplication (Handbook of Applied Cryptography 14.36). A residue x is kept as
+/// xR mod m, and multiplying two of those then reducing gives (xy)R mod m again
+#[derive(Clone, Copy)]
+pub struct MontgomeryContext {
+    modulus: Key,
+    limbs: usize,
+    // -m^-1 (mod 2^64)
+    inverse: u64,
+    // R^2 mod m, for converting into Montgomery form. R mod m is one reduction of it, so leaving it
+    // out keeps this the size of a BarrettContext, and ModularContext small without a Box
+    r_squared: Key
+}
+impl MontgomeryContext {
+    /// A context for an odd modulus bigmod can work with
+    pub fn new(modulus: Key) -> Result<Self, Error> {
+        check_modulus(modulus)?;
+        if (modulus & Key::ONE) == Key::ZERO { return Err(Error::InvalidModulus); }
+        Ok(Self::new_unchecked(modulus))
+    }
+    pub(crate) fn new_unchecked(modulus: Key) -> Self {
+        let bits = modulus.to_bits();
+        let limbs: usize = multiply::significant_limbs(bits.digits());
+        let inverse: u64 = multiply::montgomery_inverse(bits.digits()[0]);
+
+        let wide_modulus: WideKey = widen(modulus);
+        let one: Key = narrow((WideKey::ONE << (64 * limbs as u32)) % wide_modulus);
+        let r_squared: Key = narrow(widening_square(one) % wide_modulus);
+        Self { modulus, limbs, inverse, r_squared }
+    }
+    /// The modulus the context reduces by
+    pub fn modulus(&self) -> Key {
+        self.modulus
+    }
+    /// 1 in Montgomery form, which takes a reduction, so callers keep hold of it
+    pub fn one(&self) -> Key {
+        self.reduce(widen(self.r_squared))
+    }
+    /// -1 in Montgomery form, which takes a reduction too
+    pub fn minus_one(&self) -> Key {
+        let one: Key = self.one();
+        if one == Key::ZERO { Key::ZERO } else { self.modulus - one }
+    }
+
+    /// tR^-1 mod m, for t < mR
+    pub fn reduce(&self, mut t: WideKey) -> Key {
+        let modulus = self.modulus.to_bits();
+        multiply::montgomery_reduce(t.digits_mut(), &modulus.digits()[..self.limbs], self.inverse);
+        // Less than 2m, which still fits in a WideKey, and in a Key once the modulus is taken off
+        let mut reduced: WideKey = t >> (64 * self.limbs as u32);
+        let wide_modulus: WideKey = widen(self.modulus);
+        if reduced >= wide_modulus { reduced -= wide_modulus; }
+        narrow(reduced)
+    }
+    /// xR mod m, for a residue x
+    pub fn to_montgomery(&self, x: Key) -> Key {
+        self.reduce(widening_mul(x, self.r_squared))
+    }
+    /// x out of Montgomery form
+    pub fn from_montgomery(&self, x: Key) -> Key {
+        self.reduce(widen(x))
+    }
+    /// Products of numbers already in Montgomery form
+    pub fn mul(&self, a: Key, b: Key) -> Key {
+        self.reduce(widening_mul(a, b))
+    }
+    /// The square of a number already in Montgomery form
+    pub fn square(&self, a: Key) -> Key {
+        self.reduce(widening_square(a))
+    }
+    /// base^exponent with base and the result in Montgomery form
+    pub fn pow_montgomery(&self, base: Key, exponent: Key) -> Key {
+        let mut result: Key = self.one();
+        for bit in (0..exponent.bits()).rev() {
+            result = self.square(result);
+            if exponent.bit(bit) { result = self.mul(result, base); }
+        }
+        result
+    }
+    /// base^exponent for an ordinary 0 <= base < m, converting in and out
+    pub fn pow(&self, base: Key, exponent: Key) -> Key {
+        self.from_montgomery(self.pow_montgomery(self.to_montgomery(base), exponent))
+    }
+}
+
+/// Whichever of Barrett and Montgomery suits the modulus and the work. Both take ordinary
+/// residues and give ordinary results, so callers don't need to know which it is
+#[derive(Clone, Copy)]
+pub enum ModularContext {
+    /// For even moduli, or only a few products
+    Barrett(BarrettContext),
+    /// For odd moduli and enough products to pay for the conversions
+    Montgomery(MontgomeryContext)
+}
+impl ModularContext {
+    /// Montgomery for an odd modulus that's going to be used for enough products, Barrett otherwise
+    pub fn new(modulus: Key, operations: usize) -> Result<Self, Error> {
+        check_modulus(modulus)?;
+        Ok(Self::new_unchecked(modulus, operations))
+    }
+    pub(crate) fn new_unchecked(modulus: Key, operations: usize) -> Self {
+        if (modulus & Key::ONE) == Key::ONE && operations >= MONTGOMERY_MIN_OPERATIONS {
+            ModularContext::Montgomery(MontgomeryContext::new_unchecked(modulus))
+        }
+        else {
+            ModularContext::Barrett(BarrettContext::new_unchecked(modulus))
+        }
+    }
+    /// A context for computing base^exponent, which takes up to two products per exponent bit
+    pub fn for_exponent(modulus: Key, exponent: Key) -> Result<Self, Error> {
+        Self::new(modulus, 2 * exponent.bits() as usize)
+    }
+
+    /// The modulus the context reduces by
+    pub fn modulus(&self) -> Key {
+        match self {
+            ModularContext::Barrett(context) => context.modulus(),
+            ModularContext::Montgomery(context) => context.modulus()
+        }
+    }
+    /// base^exponent, for 0 <= base < m and exponent >= 0
+    pub fn pow(&self, base: Key, exponent: Key) -> Key {
+        match self {
+            ModularContext::Barrett(context) => context.pow(base, exponent),
+            ModularContext::Montgomery(context) => context.pow(base, exponent)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keygen::{ NumberHandler, bigmod, mul_mod };
+
+    // Square and multiply with a full division every time, which doesn't touch either context
+    fn pow_by_division(base: Key, exponent: Key, modulus: Key) -> Key {
+        let mut result: Key = Key::ONE % modulus;
+        for bit in (0..exponent.bits()).rev() {
+            result = mul_mod(result, result, modulus);
+            if exponent.bit(bit) { result = mul_mod(result, base, modulus); }
+        }
+        result
+    }
+
+    // Random moduli of every size, odd and even, and a few whose top bits aren't set, which
+    // get_random_n_bit_key never gives
+    fn moduli() -> Vec<Key> {
+        let mut handler = NumberHandler::new(32).unwrap();
+        let mut moduli: Vec<Key> = vec![Key::ONE, Key::TWO, Key::THREE, Key::from(u64::MAX), (Key::ONE << 64u32) + Key::ONE,
+            Key::ONE << 128u32, (Key::ONE << (MAX_MODULUS_BITS - 1)) + Key::ONE];
+        for bits in [2, 3, 63, 64, 65, 127, 128, 129, 511, 512, 1024, 2048, 2049, 3072, MAX_MODULUS_BITS] {
+            let modulus: Key = handler.get_random_n_bit_key(bits).unwrap();
+            moduli.push(modulus | Key::ONE);
+            moduli.push(modulus & !Key::ONE);
+        }
+        moduli
+    }
+
+    #[test]
+    fn contexts_match_plain_modexp() {
+        let mut handler = NumberHandler::new(32).unwrap();
+        for modulus in moduli() {
+            let base: Key = if modulus == Key::ONE { Key::ZERO } else { handler.get_random_in_range(Key::ZERO..modulus).unwrap() };
+            let exponent: Key = handler.get_random_n_bit_key(modulus.bits().clamp(2, 256)).unwrap();
+            let expected: Key = pow_by_division(base, exponent, modulus);
+
+            assert_eq!(BarrettContext::new(modulus).unwrap().pow(base, exponent), expected, "Barrett, {} bits", modulus.bits());
+            if (modulus & Key::ONE) == Key::ONE {
+                assert_eq!(MontgomeryContext::new(modulus).unwrap().pow(base, exponent), expected, "Montgomery, {} bits", modulus.bits());
+            }
+            else {
+                assert!(MontgomeryContext::new(modulus).is_err());
+            }
+            for operations in [1, MONTGOMERY_MIN_OPERATIONS] {
+                assert_eq!(ModularContext::new(modulus, operations).unwrap().pow(base, exponent), expected);
+            }
+            assert_eq!(bigmod(base, exponent, modulus), Ok(expected));
+            // The edges of the exponent and base
+            assert_eq!(BarrettContext::new(modulus).unwrap().pow(base, Key::ZERO), Key::ONE % modulus);
+            assert_eq!(ModularContext::new(modulus, MONTGOMERY_MIN_OPERATIONS).unwrap().pow(modulus - Key::ONE, Key::TWO),
+                pow_by_division(modulus - Key::ONE, Key::TWO, modulus));
+        }
+    }
+
+    #[test]
+    fn barrett_reduces_the_largest_products() {
+        for modulus in moduli() {
+            let context: BarrettContext = BarrettContext::new(modulus).unwrap();
+            let largest: Key = modulus - Key::ONE;
+            assert_eq!(context.mul_mod(largest, largest), mul_mod(largest, largest, modulus));
+            assert_eq!(context.square_mod(largest), mul_mod(largest, largest, modulus));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_moduli() {
+        for modulus in [Key::ZERO, Key::NEG_ONE, Key::ONE << MAX_MODULUS_BITS] {
+            assert!(BarrettContext::new(modulus).is_err());
+            assert!(MontgomeryContext::new(modulus).is_err());
+            assert!(ModularContext::new(modulus, 1).is_err());
+        }
+    }
+}
@@ -0,0 +1,308 @@
+/* Multiplying big integers stored as little-endian u64 limbs, for the products bigmod needs.
+    Schoolbook multiplication is quadratic in the number of limbs. Karatsuba splits each number in
+    half and gets by with three half-size products instead of four, which pays for its extra additions
+    above KARATSUBA_THRESHOLD limbs. Squaring has its own path for both, since a * a only needs
+    each cross product once. Nothing here depends on the Key type, so it works for any width.
+    Without alloc, callers bring their own scratch space, sized with scratch_limbs. */
+
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Below this many limbs in the shorter number, schoolbook is faster. Tuned with benches/multiply.rs
+pub const KARATSUBA_THRESHOLD: usize = 48;
+/// Squaring does less work per limb, so Karatsuba starts paying off a little later
+pub const KARATSUBA_SQUARE_THRESHOLD: usize = 96;
+
+/// The number of limbs once leading zero limbs are dropped
+pub fn significant_limbs(limbs: &[u64]) -> usize {
+    limbs.iter().rposition(|&limb| limb != 0).map_or(0, |top| top + 1)
+}
+
+/// out = a * b. out has to have exactly a.len() + b.len() limbs
+#[cfg(feature = "alloc")]
+pub fn mul(a: &[u64], b: &[u64], out: &mut [u64]) {
+    debug_assert_eq!(out.len(), a.len() + b.len());
+    let mut scratch: Vec<u64> = vec![0; scratch_limbs(a.len().max(b.len()))];
+    mul_with_scratch(a, b, out, &mut scratch);
+}
+
+/// out = a * a. out has to have exactly 2 * a.len() limbs
+#[cfg(feature = "alloc")]
+pub fn square(a: &[u64], out: &mut [u64]) {
+    debug_assert_eq!(out.len(), 2 * a.len());
+    let mut scratch: Vec<u64> = vec![0; scratch_limbs(a.len())];
+    square_with_scratch(a, out, &mut scratch);
+}
+
+/// Karatsuba needs room for the two sums and their product at each level, about 4n limbs
+/// in total. Allocating it once up front saves allocating at every level. const, so a fixed
+/// width like a Key can keep its scratch space on the stack
+pub const fn scratch_limbs(limbs: usize) -> usize {
+    let smallest_threshold: usize =
+        if KARATSUBA_THRESHOLD < KARATSUBA_SQUARE_THRESHOLD { KARATSUBA_THRESHOLD } else { KARATSUBA_SQUARE_THRESHOLD };
+    let mut total: usize = 0;
+    let mut length: usize = limbs;
+    while length >= smallest_threshold {
+        let half: usize = length.div_ceil(2);
+        total += 4 * (half + 1);
+        length = half + 1;
+    }
+    total
+}
+
+/// mul, with scratch at least scratch_limbs of the longer number long
+pub fn mul_with_scratch(a: &[u64], b: &[u64], out: &mut [u64], scratch: &mut [u64]) {
+    // Keep a the longer of the two
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+
+    if b.len() < KARATSUBA_THRESHOLD {
+        mul_schoolbook(a, b, out);
+    }
+    else if b.len() <= a.len() / 2 {
+        mul_unbalanced(a, b, out, scratch);
+    }
+    else {
+        mul_karatsuba(a, b, out, scratch);
+    }
+}
+/// square, with scratch at least scratch_limbs(a.len()) long
+pub fn square_with_scratch(a: &[u64], out: &mut [u64], scratch: &mut [u64]) {
+    if a.len() < KARATSUBA_SQUARE_THRESHOLD {
+        square_schoolbook(a, out);
+    }
+    else {
+        square_karatsuba(a, out, scratch);
+    }
+}
+
+/// out = a * b the O(n^2) way, which beats Karatsuba for short numbers
+pub fn mul_schoolbook(a: &[u64], b: &[u64], out: &mut [u64]) {
+    out.fill(0);
+    for (i, &limb_a) in a.iter().enumerate() {
+        let mut carry: u64 = 0;
+        for (j, &limb_b) in b.iter().enumerate() {
+            let (low, high) = mul_add_carry(limb_a, limb_b, out[i + j], carry);
+            out[i + j] = low;
+            carry = high;
+        }
+        out[i + b.len()] = carry;
+    }
+}
+
+/// Every cross product `a[i] * a[j]` with i < j, doubled, plus the squares on the diagonal
+pub fn square_schoolbook(a: &[u64], out: &mut [u64]) {
+    out.fill(0);
+    for i in 0..a.len() {
+        let mut carry: u64 = 0;
+        for j in (i + 1)..a.len() {
+            let (low, high) = mul_add_carry(a[i], a[j], out[i + j], carry);
+            out[i + j] = low;
+            carry = high;
+        }
+        out[i + a.len()] = carry;
+    }
+    shift_left_one(out);
+
+    let mut carry: u64 = 0;
+    for (i, &limb) in a.iter().enumerate() {
+        let square: u128 = limb as u128 * limb as u128;
+        let low: u128 = out[2 * i] as u128 + (square as u64) as u128 + carry as u128;
+        out[2 * i] = low as u64;
+        let high: u128 = out[2 * i + 1] as u128 + (square >> 64) + (low >> 64);
+        out[2 * i + 1] = high as u64;
+        carry = (high >> 64) as u64;
+    }
+}
+
+// a = a1 * B^half + a0 and b = b1 * B^half + b0, where B is 2^64. Then
+//   a * b = z2 * B^(2 half) + (z1 - z2 - z0) * B^half + z0
+// with z0 = a0 * b0, z2 = a1 * b1 and z1 = (a0 + a1)(b0 + b1)
+fn mul_karatsuba(a: &[u64], b: &[u64], out: &mut [u64], scratch: &mut [u64]) {
+    // We only get here when b is longer than a / 2, so b0 gets all of its half and b1 might be empty
+    let half: usize = a.len().div_ceil(2);
+    let (a0, a1) = a.split_at(half);
+    let (b0, b1) = b.split_at(half);
+
+    let (sum_a, scratch) = scratch.split_at_mut(half + 1);
+    let (sum_b, scratch) = scratch.split_at_mut(half + 1);
+    let (z1, scratch) = scratch.split_at_mut(2 * (half + 1));
+    add(a0, a1, sum_a);
+    add(b0, b1, sum_b);
+    mul_with_scratch(sum_a, sum_b, z1, scratch);
+
+    let (z0, z2) = out.split_at_mut(2 * half);
+    mul_with_scratch(a0, b0, z0, scratch);
+    mul_with_scratch(a1, b1, z2, scratch);
+    subtract_in_place(z1, z0);
+    subtract_in_place(z1, z2);
+    let z1_limbs: usize = significant_limbs(z1);
+    add_in_place(&mut out[half..], &z1[..z1_limbs]);
+}
+
+// The same split for a * a, where z1 = (a0 + a1)^2 and every product is a square
+fn square_karatsuba(a: &[u64], out: &mut [u64], scratch: &mut [u64]) {
+    let half: usize = a.len().div_ceil(2);
+    let (a0, a1) = a.split_at(half);
+
+    let (sum, scratch) = scratch.split_at_mut(half + 1);
+    let (z1, scratch) = scratch.split_at_mut(2 * (half + 1));
+    add(a0, a1, sum);
+    square_with_scratch(sum, z1, scratch);
+
+    let (z0, z2) = out.split_at_mut(2 * half);
+    square_with_scratch(a0, z0, scratch);
+    square_with_scratch(a1, z2, scratch);
+    subtract_in_place(z1, z0);
+    subtract_in_place(z1, z2);
+    let z1_limbs: usize = significant_limbs(z1);
+    add_in_place(&mut out[half..], &z1[..z1_limbs]);
+}
+
+// When b is much shorter than a, split a into pieces as long as b so each product is balanced.
+// a is at least twice as long as b, so its scratch has room for a piece's product and the
+// scratch that product needs
+fn mul_unbalanced(a: &[u64], b: &[u64], out: &mut [u64], scratch: &mut [u64]) {
+    out.fill(0);
+    let (piece_product, scratch) = scratch.split_at_mut(2 * b.len());
+    for (index, piece) in a.chunks(b.len()).enumerate() {
+        let product: &mut [u64] = &mut piece_product[..(piece.len() + b.len())];
+        mul_with_scratch(piece, b, product, scratch);
+        add_in_place(&mut out[(index * b.len())..], product);
+    }
+}
+
+// (low, high) of a * b + addend + carry, which can't overflow 128 bits
+#[inline]
+fn mul_add_carry(a: u64, b: u64, addend: u64, carry: u64) -> (u64, u64) {
+    let total: u128 = a as u128 * b as u128 + addend as u128 + carry as u128;
+    (total as u64, (total >> 64) as u64)
+}
+
+// sum = a + b, where a is at least as long as b and sum has one more limb than a for the carry
+fn add(a: &[u64], b: &[u64], sum: &mut [u64]) {
+    sum.fill(0);
+    sum[..a.len()].copy_from_slice(a);
+    add_in_place(sum, b);
+}
+// target += addend. The caller makes sure target is long enough for the carry
+fn add_in_place(target: &mut [u64], addend: &[u64]) {
+    let mut carry: bool = false;
+    for (ind, limb) in target.iter_mut().enumerate() {
+        if ind >= addend.len() && !carry { break; }
+        let (sum, overflow_a) = limb.overflowing_add(*addend.get(ind).unwrap_or(&0));
+        let (sum, overflow_b) = sum.overflowing_add(carry as u64);
+        *limb = sum;
+        carry = overflow_a || overflow_b;
+    }
+    debug_assert!(!carry);
+}
+// target -= subtrahend. The caller makes sure target is the bigger number
+fn subtract_in_place(target: &mut [u64], subtrahend: &[u64]) {
+    let mut borrow: bool = false;
+    for (ind, limb) in target.iter_mut().enumerate() {
+        if ind >= subtrahend.len() && !borrow { break; }
+        let (difference, overflow_a) = limb.overflowing_sub(*subtrahend.get(ind).unwrap_or(&0));
+        let (difference, overflow_b) = difference.overflowing_sub(borrow as u64);
+        *limb = difference;
+        borrow = overflow_a || overflow_b;
+    }
+    debug_assert!(!borrow);
+}
+fn shift_left_one(limbs: &mut [u64]) {
+    let mut carry: u64 = 0;
+    for limb in limbs.iter_mut() {
+        let next_carry: u64 = *limb >> 63;
+        *limb = (*limb << 1) | carry;
+        carry = next_carry;
+    }
+}
+
+/// Montgomery reduction (REDC) in place. t holds a number below modulus * R, where R = 2^(64 k) and
+/// k = modulus.len(), and needs room for at least 2k limbs plus a carry. Afterwards t[k..] holds
+/// t / R (mod modulus), which is below 2 * modulus. inverse is -modulus^-1 (mod 2^64).
+/// Each pass adds the multiple of the modulus that clears the lowest limb, so nothing gets divided
+pub fn montgomery_reduce(t: &mut [u64], modulus: &[u64], inverse: u64) {
+    let limbs: usize = modulus.len();
+    for i in 0..limbs {
+        let factor: u64 = t[i].wrapping_mul(inverse);
+        let mut carry: u64 = 0;
+        for (j, &limb) in modulus.iter().enumerate() {
+            let (low, high) = mul_add_carry(factor, limb, t[i + j], carry);
+            t[i + j] = low;
+            carry = high;
+        }
+        let mut ind: usize = i + limbs;
+        while carry != 0 {
+            let (sum, overflow) = t[ind].overflowing_add(carry);
+            t[ind] = sum;
+            carry = overflow as u64;
+            ind += 1;
+        }
+    }
+}
+
+/// -modulus^-1 (mod 2^64) for an odd modulus. Each Newton step doubles the number of correct low
+/// bits, and modulus is its own inverse to 3 bits, so five steps are enough for 64
+pub fn montgomery_inverse(lowest_limb: u64) -> u64 {
+    debug_assert!(lowest_limb & 1 == 1);
+    let mut inverse: u64 = lowest_limb;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(lowest_limb.wrapping_mul(inverse)));
+    }
+    inverse.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    fn random_limbs(length: usize) -> Vec<u64> {
+        let mut rng = rand::rng();
+        (0..length).map(|_| rng.next_u64()).collect()
+    }
+    // Lengths either side of both thresholds, and one long enough to recurse more than once
+    const LENGTHS: [usize; 8] = [1, 47, 48, 49, 95, 96, 97, 200];
+
+    #[test]
+    fn karatsuba_matches_schoolbook() {
+        for length_a in LENGTHS {
+            for length_b in LENGTHS {
+                // All ones as well, for the longest carries
+                for (a, b) in [(random_limbs(length_a), random_limbs(length_b)), (vec![u64::MAX; length_a], vec![u64::MAX; length_b])] {
+                    let mut expected: Vec<u64> = vec![0; length_a + length_b];
+                    let mut product: Vec<u64> = vec![0; length_a + length_b];
+                    mul_schoolbook(&a, &b, &mut expected);
+                    mul(&a, &b, &mut product);
+                    assert_eq!(product, expected, "{} by {} limbs", length_a, length_b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn karatsuba_square_matches_schoolbook() {
+        for length in LENGTHS {
+            for a in [random_limbs(length), vec![u64::MAX; length]] {
+                let mut expected: Vec<u64> = vec![0; 2 * length];
+                let mut product: Vec<u64> = vec![0; 2 * length];
+                mul_schoolbook(&a, &a, &mut expected);
+                square_schoolbook(&a, &mut product);
+                assert_eq!(product, expected, "{} limbs by schoolbook", length);
+                square(&a, &mut product);
+                assert_eq!(product, expected, "{} limbs", length);
+            }
+        }
+    }
+
+    #[test]
+    fn montgomery_inverse_is_the_negated_inverse() {
+        let mut rng = rand::rng();
+        for _ in 0..100 {
+            let odd: u64 = rng.next_u64() | 1;
+            assert_eq!(odd.wrapping_mul(montgomery_inverse(odd)), u64::MAX);
+        }
+    }
+}
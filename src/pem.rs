@@ -0,0 +1,66 @@
+// PEM armor (RFC 7468): base64 DER between "-----BEGIN <label>-----" and "-----END <label>-----" lines
+
+use crate::base64;
+use crate::base64::Base64Error;
+
+/// Why some text couldn't be read as PEM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PemError {
+    /// No BEGIN line
+    MissingBegin,
+    /// No END line after the BEGIN line
+    MissingEnd,
+    /// The END line's label doesn't match the BEGIN line's
+    LabelMismatch,
+    /// The base64 between the lines is malformed
+    Base64(Base64Error)
+}
+impl From<Base64Error> for PemError {
+    fn from(error: Base64Error) -> Self {
+        PemError::Base64(error)
+    }
+}
+
+/// Wrap the data in PEM armor, with the base64 split into 64 character lines
+pub fn encode(label: &str, data: &[u8]) -> String {
+    encode_with_width(label, data, 64)
+}
+/// OpenSSH uses the same armor for its private keys, but with 70 character lines
+pub fn encode_with_width(label: &str, data: &[u8], width: usize) -> String {
+    let body: String = base64::encode(data);
+    let mut output: String = format!("-----BEGIN {}-----\n", label);
+    for line in body.as_bytes().chunks(width) {
+        // The base64 alphabet is all ASCII, so any split is valid UTF-8
+        output.push_str(std::str::from_utf8(line).unwrap());
+        output.push('\n');
+    }
+    output.push_str(&format!("-----END {}-----\n", label));
+    output
+}
+
+/// Decode the first PEM block in the text, returning its label and data.
+/// Anything before the BEGIN line is ignored, like OpenSSL does
+pub fn decode(text: &str) -> Result<(String, Vec<u8>), PemError> {
+    let mut lines = text.lines().map(|line| line.trim());
+
+    let label: &str = loop {
+        match lines.next() {
+            None => return Err(PemError::MissingBegin),
+            Some(line) => {
+                if let Some(label) = line.strip_prefix("-----BEGIN ").and_then(|rest| rest.strip_suffix("-----")) {
+                    break label;
+                }
+            }
+        }
+    };
+
+    let mut body: String = String::new();
+    for line in lines {
+        if let Some(end_label) = line.strip_prefix("-----END ").and_then(|rest| rest.strip_suffix("-----")) {
+            if end_label != label { return Err(PemError::LabelMismatch); }
+            return Ok((label.to_string(), base64::decode(&body)?));
+        }
+        body.push_str(line);
+    }
+    Err(PemError::MissingEnd)
+}
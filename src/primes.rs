@@ -1,75 +1,430 @@
-// Even though it would save work to save a list of these as type Key's,
-// it causes a stack overflow with so many primes.
-// We also don't need 2, since key generation already ensures the key is odd
-pub const FIRST_PRIMES: [u64; 1388] = [
-    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
-    73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173,
-    179, 181, 191, 193, 197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281,
-    283, 293, 307, 311, 313, 317, 331, 337, 347, 349, 353, 359, 367, 373, 379, 383, 389, 397, 401, 409,
-    419, 421, 431, 433, 439, 443, 449, 457, 461, 463, 467, 479, 487, 491, 499, 503, 509, 521, 523, 541,
-    547, 557, 563, 569, 571, 577, 587, 593, 599, 601, 607, 613, 617, 619, 631, 641, 643, 647, 653, 659,
-    661, 673, 677, 683, 691, 701, 709, 719, 727, 733, 739, 743, 751, 757, 761, 769, 773, 787, 797, 809,
-    811, 821, 823, 827, 829, 839, 853, 857, 859, 863, 877, 881, 883, 887, 907, 911, 919, 929, 937, 941,
-    947, 953, 967, 971, 977, 983, 991, 997, 1009, 1013, 1019, 1021, 1031, 1033, 1039, 1049, 1051, 1061, 1063, 1069,
-    1087, 1091, 1093, 1097, 1103, 1109, 1117, 1123, 1129, 1151, 1153, 1163, 1171, 1181, 1187, 1193, 1201, 1213, 1217, 1223,
-    1229, 1231, 1237, 1249, 1259, 1277, 1279, 1283, 1289, 1291, 1297, 1301, 1303, 1307, 1319, 1321, 1327, 1361, 1367, 1373,
-    1381, 1399, 1409, 1423, 1427, 1429, 1433, 1439, 1447, 1451, 1453, 1459, 1471, 1481, 1483, 1487, 1489, 1493, 1499, 1511,
-    1523, 1531, 1543, 1549, 1553, 1559, 1567, 1571, 1579, 1583, 1597, 1601, 1607, 1609, 1613, 1619, 1621, 1627, 1637, 1657,
-    1663, 1667, 1669, 1693, 1697, 1699, 1709, 1721, 1723, 1733, 1741, 1747, 1753, 1759, 1777, 1783, 1787, 1789, 1801, 1811,
-    1823, 1831, 1847, 1861, 1867, 1871, 1873, 1877, 1879, 1889, 1901, 1907, 1913, 1931, 1933, 1949, 1951, 1973, 1979, 1987,
-    1993, 1997, 1999, 2003, 2011, 2017, 2027, 2029, 2039, 2053, 2063, 2069, 2081, 2083, 2087, 2089, 2099, 2111, 2113, 2129,
-    2131, 2137, 2141, 2143, 2153, 2161, 2179, 2203, 2207, 2213, 2221, 2237, 2239, 2243, 2251, 2267, 2269, 2273, 2281, 2287,
-    2293, 2297, 2309, 2311, 2333, 2339, 2341, 2347, 2351, 2357, 2371, 2377, 2381, 2383, 2389, 2393, 2399, 2411, 2417, 2423,
-    2437, 2441, 2447, 2459, 2467, 2473, 2477, 2503, 2521, 2531, 2539, 2543, 2549, 2551, 2557, 2579, 2591, 2593, 2609, 2617,
-    2621, 2633, 2647, 2657, 2659, 2663, 2671, 2677, 2683, 2687, 2689, 2693, 2699, 2707, 2711, 2713, 2719, 2729, 2731, 2741,
-    2749, 2753, 2767, 2777, 2789, 2791, 2797, 2801, 2803, 2819, 2833, 2837, 2843, 2851, 2857, 2861, 2879, 2887, 2897, 2903,
-    2909, 2917, 2927, 2939, 2953, 2957, 2963, 2969, 2971, 2999, 3001, 3011, 3019, 3023, 3037, 3041, 3049, 3061, 3067, 3079,
-    3083, 3089, 3109, 3119, 3121, 3137, 3163, 3167, 3169, 3181, 3187, 3191, 3203, 3209, 3217, 3221, 3229, 3251, 3253, 3257,
-    3259, 3271, 3299, 3301, 3307, 3313, 3319, 3323, 3329, 3331, 3343, 3347, 3359, 3361, 3371, 3373, 3389, 3391, 3407, 3413,
-    3433, 3449, 3457, 3461, 3463, 3467, 3469, 3491, 3499, 3511, 3517, 3527, 3529, 3533, 3539, 3541, 3547, 3557, 3559, 3571,
-    3581, 3583, 3593, 3607, 3613, 3617, 3623, 3631, 3637, 3643, 3659, 3671, 3673, 3677, 3691, 3697, 3701, 3709, 3719, 3727,
-    3733, 3739, 3761, 3767, 3769, 3779, 3793, 3797, 3803, 3821, 3823, 3833, 3847, 3851, 3853, 3863, 3877, 3881, 3889, 3907,
-    3911, 3917, 3919, 3923, 3929, 3931, 3943, 3947, 3967, 3989, 4001, 4003, 4007, 4013, 4019, 4021, 4027, 4049, 4051, 4057,
-    4073, 4079, 4091, 4093, 4099, 4111, 4127, 4129, 4133, 4139, 4153, 4157, 4159, 4177, 4201, 4211, 4217, 4219, 4229, 4231,
-    4241, 4243, 4253, 4259, 4261, 4271, 4273, 4283, 4289, 4297, 4327, 4337, 4339, 4349, 4357, 4363, 4373, 4391, 4397, 4409,
-    4421, 4423, 4441, 4447, 4451, 4457, 4463, 4481, 4483, 4493, 4507, 4513, 4517, 4519, 4523, 4547, 4549, 4561, 4567, 4583,
-    4591, 4597, 4603, 4621, 4637, 4639, 4643, 4649, 4651, 4657, 4663, 4673, 4679, 4691, 4703, 4721, 4723, 4729, 4733, 4751,
-    4759, 4783, 4787, 4789, 4793, 4799, 4801, 4813, 4817, 4831, 4861, 4871, 4877, 4889, 4903, 4909, 4919, 4931, 4933, 4937,
-    4943, 4951, 4957, 4967, 4969, 4973, 4987, 4993, 4999, 5003, 5009, 5011, 5021, 5023, 5039, 5051, 5059, 5077, 5081, 5087,
-    5099, 5101, 5107, 5113, 5119, 5147, 5153, 5167, 5171, 5179, 5189, 5197, 5209, 5227, 5231, 5233, 5237, 5261, 5273, 5279,
-    5281, 5297, 5303, 5309, 5323, 5333, 5347, 5351, 5381, 5387, 5393, 5399, 5407, 5413, 5417, 5419, 5431, 5437, 5441, 5443,
-    5449, 5471, 5477, 5479, 5483, 5501, 5503, 5507, 5519, 5521, 5527, 5531, 5557, 5563, 5569, 5573, 5581, 5591, 5623, 5639,
-    5641, 5647, 5651, 5653, 5657, 5659, 5669, 5683, 5689, 5693, 5701, 5711, 5717, 5737, 5741, 5743, 5749, 5779, 5783, 5791,
-    5801, 5807, 5813, 5821, 5827, 5839, 5843, 5849, 5851, 5857, 5861, 5867, 5869, 5879, 5881, 5897, 5903, 5923, 5927, 5939,
-    5953, 5981, 5987, 6007, 6011, 6029, 6037, 6043, 6047, 6053, 6067, 6073, 6079, 6089, 6091, 6101, 6113, 6121, 6131, 6133,
-    6143, 6151, 6163, 6173, 6197, 6199, 6203, 6211, 6217, 6221, 6229, 6247, 6257, 6263, 6269, 6271, 6277, 6287, 6299, 6301,
-    6311, 6317, 6323, 6329, 6337, 6343, 6353, 6359, 6361, 6367, 6373, 6379, 6389, 6397, 6421, 6427, 6449, 6451, 6469, 6473,
-    6481, 6491, 6521, 6529, 6547, 6551, 6553, 6563, 6569, 6571, 6577, 6581, 6599, 6607, 6619, 6637, 6653, 6659, 6661, 6673,
-    6679, 6689, 6691, 6701, 6703, 6709, 6719, 6733, 6737, 6761, 6763, 6779, 6781, 6791, 6793, 6803, 6823, 6827, 6829, 6833,
-    6841, 6857, 6863, 6869, 6871, 6883, 6899, 6907, 6911, 6917, 6947, 6949, 6959, 6961, 6967, 6971, 6977, 6983, 6991, 6997,
-    7001, 7013, 7019, 7027, 7039, 7043, 7057, 7069, 7079, 7103, 7109, 7121, 7127, 7129, 7151, 7159, 7177, 7187, 7193, 7207,
-    7211, 7213, 7219, 7229, 7237, 7243, 7247, 7253, 7283, 7297, 7307, 7309, 7321, 7331, 7333, 7349, 7351, 7369, 7393, 7411,
-    7417, 7433, 7451, 7457, 7459, 7477, 7481, 7487, 7489, 7499, 7507, 7517, 7523, 7529, 7537, 7541, 7547, 7549, 7559, 7561,
-    7573, 7577, 7583, 7589, 7591, 7603, 7607, 7621, 7639, 7643, 7649, 7669, 7673, 7681, 7687, 7691, 7699, 7703, 7717, 7723,
-    7727, 7741, 7753, 7757, 7759, 7789, 7793, 7817, 7823, 7829, 7841, 7853, 7867, 7873, 7877, 7879, 7883, 7901, 7907, 7919,
-    7927, 7933, 7937, 7949, 7951, 7963, 7993, 8009, 8011, 8017, 8039, 8053, 8059, 8069, 8081, 8087, 8089, 8093, 8101, 8111,
-    8117, 8123, 8147, 8161, 8167, 8171, 8179, 8191, 8209, 8219, 8221, 8231, 8233, 8237, 8243, 8263, 8269, 8273, 8287, 8291,
-    8293, 8297, 8311, 8317, 8329, 8353, 8363, 8369, 8377, 8387, 8389, 8419, 8423, 8429, 8431, 8443, 8447, 8461, 8467, 8501,
-    8513, 8521, 8527, 8537, 8539, 8543, 8563, 8573, 8581, 8597, 8599, 8609, 8623, 8627, 8629, 8641, 8647, 8663, 8669, 8677,
-    8681, 8689, 8693, 8699, 8707, 8713, 8719, 8731, 8737, 8741, 8747, 8753, 8761, 8779, 8783, 8803, 8807, 8819, 8821, 8831,
-    8837, 8839, 8849, 8861, 8863, 8867, 8887, 8893, 8923, 8929, 8933, 8941, 8951, 8963, 8969, 8971, 8999, 9001, 9007, 9011,
-    9013, 9029, 9041, 9043, 9049, 9059, 9067, 9091, 9103, 9109, 9127, 9133, 9137, 9151, 9157, 9161, 9173, 9181, 9187, 9199,
-    9203, 9209, 9221, 9227, 9239, 9241, 9257, 9277, 9281, 9283, 9293, 9311, 9319, 9323, 9337, 9341, 9343, 9349, 9371, 9377,
-    9391, 9397, 9403, 9413, 9419, 9421, 9431, 9433, 9437, 9439, 9461, 9463, 9467, 9473, 9479, 9491, 9497, 9511, 9521, 9533,
-    9539, 9547, 9551, 9587, 9601, 9613, 9619, 9623, 9629, 9631, 9643, 9649, 9661, 9677, 9679, 9689, 9697, 9719, 9721, 9733,
-    9739, 9743, 9749, 9767, 9769, 9781, 9787, 9791, 9803, 9811, 9817, 9829, 9833, 9839, 9851, 9857, 9859, 9871, 9883, 9887,
-    9901, 9907, 9923, 9929, 9931, 9941, 9949, 9967, 9973, 10007, 10009, 10037, 10039, 10061, 10067, 10069, 10079, 10091, 10093, 10099,
-    10103, 10111, 10133, 10139, 10141, 10151, 10159, 10163, 10169, 10177, 10181, 10193, 10211, 10223, 10243, 10247, 10253, 10259, 10267, 10271,
-    10273, 10289, 10301, 10303, 10313, 10321, 10331, 10333, 10337, 10343, 10357, 10369, 10391, 10399, 10427, 10429, 10433, 10453, 10457, 10459,
-    10463, 10477, 10487, 10499, 10501, 10513, 10529, 10531, 10559, 10567, 10589, 10597, 10601, 10607, 10613, 10627, 10631, 10639, 10651, 10657,
-    10663, 10667, 10687, 10691, 10709, 10711, 10723, 10729, 10733, 10739, 10753, 10771, 10781, 10789, 10799, 10831, 10837, 10847, 10853, 10859,
-    10861, 10867, 10883, 10889, 10891, 10903, 10909, 10937, 10939, 10949, 10957, 10973, 10979, 10987, 10993, 11003, 11027, 11047, 11057, 11059,
-    11069, 11071, 11083, 11087, 11093, 11113, 11117, 11119, 11131, 11149, 11159, 11161, 11171, 11173, 11177, 11197, 11213, 11239, 11243, 11251,
-    11257, 11261, 11273, 11279, 11287, 11299, 11311, 11317, 11321, 11329, 11351, 11353, 11369, 11383, 11393, 11399, 11411, 11423, 11437, 11443,
-    11447, 11467, 11471, 11483, 11489, 11491, 11497, 11503, 11519,
-];
\ No newline at end of file
+/* Primes that fit in a machine word. None of this needs the Key bignum.
+    The small prime tables for trial division are generated at compile time with a sieve of Eratosthenes.
+    Change SMALL_PRIME_BOUND to trade sieving time against how many candidates reach the slow tests.
+    The const assertions at the bottom check the tables against plain trial division, so a bad
+    table fails the build instead of quietly letting composites through.
+    For everything else there's a segmented sieve over any u64 range, prime counting, nth_prime,
+    and a deterministic Miller-Rabin test for u64. The sieve needs alloc for its buffers. */
+
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::ops::Range;
+
+/// Every odd prime below this goes in FIRST_PRIMES. The key validation in keygen needs a modulus
+/// bigger than the square of the largest one
+pub const SMALL_PRIME_BOUND: usize = 11520;
+
+/// How many odd primes there are below SMALL_PRIME_BOUND
+pub const FIRST_PRIME_COUNT: usize = count_odd_primes::<SMALL_PRIME_BOUND>();
+/// The odd primes below SMALL_PRIME_BOUND, for trial division.
+/// Even though it would save work to save a list of these as type Key's,
+/// it causes a stack overflow with so many primes.
+/// We also don't need 2, since key generation already ensures the key is odd
+pub const FIRST_PRIMES: [u64; FIRST_PRIME_COUNT] = odd_primes::<SMALL_PRIME_BOUND, FIRST_PRIME_COUNT>();
+
+/// How many products FIRST_PRIMES packs into
+pub const PRIME_PRODUCT_COUNT: usize = count_products(&FIRST_PRIMES);
+/// FIRST_PRIMES packed into products that each fit in a u64. A Key only has to be divided by each
+/// product once, and then gcd(remainder, product) != 1 exactly when one of its primes divides the Key
+pub const PRIME_PRODUCTS: [u64; PRIME_PRODUCT_COUNT] = prime_products::<PRIME_PRODUCT_COUNT>(&FIRST_PRIMES);
+
+/// Sieve of Eratosthenes. `composite[n]` is true when n isn't prime, for every n < LIMIT
+pub const fn sieve<const LIMIT: usize>() -> [bool; LIMIT] {
+    let mut composite: [bool; LIMIT] = [false; LIMIT];
+    if LIMIT > 0 { composite[0] = true; }
+    if LIMIT > 1 { composite[1] = true; }
+
+    let mut prime: usize = 2;
+    while prime * prime < LIMIT {
+        if !composite[prime] {
+            let mut multiple: usize = prime * prime;
+            while multiple < LIMIT {
+                composite[multiple] = true;
+                multiple += prime;
+            }
+        }
+        prime += 1;
+    }
+    composite
+}
+
+/// How many odd primes there are below LIMIT, to size odd_primes' table
+pub const fn count_odd_primes<const LIMIT: usize>() -> usize {
+    let composite: [bool; LIMIT] = sieve::<LIMIT>();
+    let mut count: usize = 0;
+    let mut num: usize = 3;
+    while num < LIMIT {
+        if !composite[num] { count += 1; }
+        num += 2;
+    }
+    count
+}
+/// The odd primes below LIMIT in order. COUNT has to be `count_odd_primes::<LIMIT>()`
+pub const fn odd_primes<const LIMIT: usize, const COUNT: usize>() -> [u64; COUNT] {
+    let composite: [bool; LIMIT] = sieve::<LIMIT>();
+    let mut primes: [u64; COUNT] = [0; COUNT];
+    let mut ind: usize = 0;
+    let mut num: usize = 3;
+    while num < LIMIT {
+        if !composite[num] {
+            primes[ind] = num as u64;
+            ind += 1;
+        }
+        num += 2;
+    }
+    assert!(ind == COUNT, "COUNT doesn't match the number of primes below LIMIT");
+    primes
+}
+
+/// Greedily pack consecutive primes into products until the next one would overflow
+pub const fn count_products(primes: &[u64]) -> usize {
+    let mut count: usize = 0;
+    let mut product: u64 = 1;
+    let mut ind: usize = 0;
+    while ind < primes.len() {
+        match product.checked_mul(primes[ind]) {
+            Some(next) => product = next,
+            None => {
+                count += 1;
+                product = primes[ind];
+            }
+        }
+        ind += 1;
+    }
+    if product > 1 { count + 1 } else { count }
+}
+/// Pack the primes into the COUNT products that count_products counted
+pub const fn prime_products<const COUNT: usize>(primes: &[u64]) -> [u64; COUNT] {
+    let mut products: [u64; COUNT] = [1; COUNT];
+    let mut group: usize = 0;
+    let mut ind: usize = 0;
+    while ind < primes.len() {
+        match products[group].checked_mul(primes[ind]) {
+            Some(next) => products[group] = next,
+            None => {
+                group += 1;
+                products[group] = primes[ind];
+            }
+        }
+        ind += 1;
+    }
+    products
+}
+
+/// Binary GCD on machine words, for checking a remainder against PRIME_PRODUCTS
+pub const fn gcd_u64(mut a: u64, mut b: u64) -> u64 {
+    if a == 0 { return b; }
+    if b == 0 { return a; }
+    let shift: u32 = (a | b).trailing_zeros();
+    a >>= a.trailing_zeros();
+    loop {
+        b >>= b.trailing_zeros();
+        if a > b {
+            let temp: u64 = a;
+            a = b;
+            b = temp;
+        }
+        b -= a;
+        if b == 0 { return a << shift; }
+    }
+}
+
+// Miller-Rabin with the first 12 primes as bases has no false positives below 3.3 * 10^24,
+// so for a u64 the answer is exact
+const DETERMINISTIC_BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+const fn mul_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    ((a as u128 * b as u128) % modulus as u128) as u64
+}
+const fn pow_mod(mut base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    let mut result: u64 = 1;
+    base %= modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 { result = mul_mod(result, base, modulus); }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Deterministic primality test for any u64
+pub const fn is_prime_u64(num: u64) -> bool {
+    if num < 2 { return false; }
+    // Small numbers and numbers with a factor among the bases
+    let mut ind: usize = 0;
+    while ind < DETERMINISTIC_BASES.len() {
+        let base: u64 = DETERMINISTIC_BASES[ind];
+        if num == base { return true; }
+        if num.is_multiple_of(base) { return false; }
+        ind += 1;
+    }
+
+    // num - 1 = mantissa * 2^exponent
+    let exponent: u32 = (num - 1).trailing_zeros();
+    let mantissa: u64 = (num - 1) >> exponent;
+    let mut ind: usize = 0;
+    'bases: while ind < DETERMINISTIC_BASES.len() {
+        let mut power: u64 = pow_mod(DETERMINISTIC_BASES[ind], mantissa, num);
+        ind += 1;
+        if power == 1 || power == num - 1 { continue; }
+        let mut square: u32 = 1;
+        while square < exponent {
+            power = mul_mod(power, power, num);
+            if power == num - 1 { continue 'bases; }
+            square += 1;
+        }
+        return false;
+    }
+    true
+}
+
+#[cfg(feature = "alloc")]
+// How many numbers each segment of the sieve covers. Small enough to stay in L1 cache
+const SEGMENT_LENGTH: u64 = 1 << 15;
+#[cfg(feature = "alloc")]
+// Segments are only sieved by primes below this. Anything left that's at least its square
+// might still be composite, so it goes through is_prime_u64. That keeps ranges up near
+// 2^64 from needing every prime up to 2^32 first
+const SIEVING_PRIME_BOUND: u64 = 1 << 20;
+
+#[cfg(feature = "alloc")]
+// Every prime below the limit, from a plain sieve
+fn primes_below(limit: u64) -> Vec<u64> {
+    let mut composite: Vec<bool> = vec![false; limit as usize];
+    let mut primes: Vec<u64> = Vec::new();
+    for num in 2..limit {
+        if composite[num as usize] { continue; }
+        primes.push(num);
+        let mut multiple: u64 = num * num;
+        while multiple < limit {
+            composite[multiple as usize] = true;
+            multiple += num;
+        }
+    }
+    primes
+}
+
+/// Integer square root, rounded down
+pub const fn isqrt_u64(num: u64) -> u64 {
+    if num < 2 { return num; }
+    // Newton's method from an overestimate, which then decreases monotonically
+    let mut root: u64 = 1 << (u64::BITS - num.leading_zeros()).div_ceil(2);
+    loop {
+        let next: u64 = (root + num / root) / 2;
+        if next >= root { return root; }
+        root = next;
+    }
+}
+
+#[cfg(feature = "alloc")]
+/// Iterates over the primes in a range in increasing order, one segment at a time, so the
+/// memory needed doesn't depend on how big the range is
+pub struct PrimeSieve {
+    sieving_primes: Vec<u64>,
+    end: u64,
+    // The segment being read, and where in it we are
+    segment_start: u64,
+    composite: Vec<bool>,
+    position: usize,
+    // Survivors in this segment at or above this still need is_prime_u64
+    unsieved_from: u128
+}
+#[cfg(feature = "alloc")]
+impl PrimeSieve {
+    /// A sieve over the primes in the range
+    pub fn new(range: Range<u64>) -> Self {
+        // Sieving primes only have to go up to the square root of the end of the range
+        let limit: u64 = (isqrt_u64(range.end) + 1).min(SIEVING_PRIME_BOUND);
+        let mut sieve = Self {
+            sieving_primes: primes_below(limit),
+            end: range.end,
+            segment_start: range.start,
+            composite: Vec::new(),
+            position: 0,
+            unsieved_from: (limit as u128) * (limit as u128)
+        };
+        sieve.sieve_segment();
+        sieve
+    }
+
+    fn sieve_segment(&mut self) {
+        let start: u64 = self.segment_start;
+        let length: u64 = SEGMENT_LENGTH.min(self.end.saturating_sub(start));
+        self.composite.clear();
+        self.composite.resize(length as usize, false);
+        self.position = 0;
+
+        for number in start..(start + length).min(2) {
+            self.composite[(number - start) as usize] = true;
+        }
+        let segment_end: u128 = start as u128 + length as u128;
+        for &prime in &self.sieving_primes {
+            let square: u128 = prime as u128 * prime as u128;
+            if square >= segment_end { break; }
+            // The first multiple of the prime in the segment, but not the prime itself
+            let first: u128 = square.max((start as u128).div_ceil(prime as u128) * prime as u128);
+            let mut multiple: u128 = first;
+            while multiple < segment_end {
+                self.composite[(multiple - start as u128) as usize] = true;
+                multiple += prime as u128;
+            }
+        }
+    }
+}
+#[cfg(feature = "alloc")]
+impl Iterator for PrimeSieve {
+    type Item = u64;
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            while self.position < self.composite.len() {
+                let number: u64 = self.segment_start + self.position as u64;
+                let composite: bool = self.composite[self.position];
+                self.position += 1;
+                if !composite && ((number as u128) < self.unsieved_from || is_prime_u64(number)) {
+                    return Some(number);
+                }
+            }
+            self.segment_start += self.composite.len() as u64;
+            if self.segment_start >= self.end { return None; }
+            self.sieve_segment();
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+/// The primes in a range, in increasing order
+pub fn primes_in(range: Range<u64>) -> PrimeSieve {
+    PrimeSieve::new(range)
+}
+
+#[cfg(feature = "alloc")]
+/// The number of primes less than or equal to num
+pub fn prime_pi(num: u64) -> u64 {
+    let mut count: u64 = primes_in(0..num).count() as u64;
+    if is_prime_u64(num) { count += 1; }
+    count
+}
+
+#[cfg(feature = "alloc")]
+/// The nth prime, counting 2 as the first. None for n = 0, or past the last prime that fits in a u64
+pub fn nth_prime(n: u64) -> Option<u64> {
+    if n == 0 { return None; }
+    primes_in(0..u64::MAX).nth((n - 1) as usize)
+}
+
+// Trial division, deliberately independent of the sieve so it can check it
+const fn is_prime_by_trial_division(num: u64) -> bool {
+    if num < 2 { return false; }
+    if num.is_multiple_of(2) { return num == 2; }
+    let mut divisor: u64 = 3;
+    while divisor * divisor <= num {
+        if num.is_multiple_of(divisor) { return false; }
+        divisor += 2;
+    }
+    true
+}
+
+// Every entry is an odd prime below the bound, they're strictly increasing, and none are missing
+const fn first_primes_are_correct() -> bool {
+    let mut ind: usize = 0;
+    let mut previous: u64 = 2;
+    while ind < FIRST_PRIMES.len() {
+        let prime: u64 = FIRST_PRIMES[ind];
+        if prime <= previous || prime as usize >= SMALL_PRIME_BOUND || !is_prime_by_trial_division(prime) { return false; }
+        // Nothing between this prime and the last one is prime
+        let mut skipped: u64 = previous + 1;
+        while skipped < prime {
+            if is_prime_by_trial_division(skipped) { return false; }
+            skipped += 1;
+        }
+        previous = prime;
+        ind += 1;
+    }
+    // Nor anything between the last prime and the bound
+    while (previous as usize) + 1 < SMALL_PRIME_BOUND {
+        previous += 1;
+        if is_prime_by_trial_division(previous) { return false; }
+    }
+    true
+}
+// Every prime goes into exactly one product, in order
+const fn prime_products_are_correct() -> bool {
+    let mut group: usize = 0;
+    let mut remaining: u64 = PRIME_PRODUCTS[0];
+    let mut ind: usize = 0;
+    while ind < FIRST_PRIMES.len() {
+        if remaining == 1 {
+            group += 1;
+            if group >= PRIME_PRODUCTS.len() { return false; }
+            remaining = PRIME_PRODUCTS[group];
+        }
+        if !remaining.is_multiple_of(FIRST_PRIMES[ind]) { return false; }
+        remaining /= FIRST_PRIMES[ind];
+        ind += 1;
+    }
+    remaining == 1 && group == PRIME_PRODUCTS.len() - 1
+}
+
+const _: () = assert!(first_primes_are_correct(), "FIRST_PRIMES is not every odd prime below SMALL_PRIME_BOUND");
+const _: () = assert!(prime_products_are_correct(), "PRIME_PRODUCTS does not cover FIRST_PRIMES");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_indexes_primes() {
+        assert_eq!(prime_pi(0), 0);
+        assert_eq!(prime_pi(2), 1);
+        assert_eq!(prime_pi(100), 25);
+        assert_eq!(prime_pi(1_000_000), 78498);
+        assert_eq!(nth_prime(0), None);
+        assert_eq!(nth_prime(1), Some(2));
+        assert_eq!(nth_prime(10_000), Some(104729));
+    }
+
+    #[test]
+    fn sieve_matches_trial_division_across_segments() {
+        // Several segments' worth, starting at 0 and part of the way into one
+        for start in [0, 1, 2, 1000, SEGMENT_LENGTH - 7] {
+            let end: u64 = 3 * SEGMENT_LENGTH + 100;
+            let expected: Vec<u64> = (start..end).filter(|num| is_prime_by_trial_division(*num)).collect();
+            assert_eq!(primes_in(start..end).collect::<Vec<u64>>(), expected, "from {}", start);
+        }
+        // Ranges that end exactly on a segment boundary, or straddle one
+        assert_eq!(primes_in((SEGMENT_LENGTH - 20)..SEGMENT_LENGTH).collect::<Vec<u64>>(), vec![32749]);
+        assert_eq!(primes_in((SEGMENT_LENGTH - 20)..(SEGMENT_LENGTH + 4)).collect::<Vec<u64>>(), vec![32749, 32771]);
+        assert_eq!(primes_in((2 * SEGMENT_LENGTH - 16)..(2 * SEGMENT_LENGTH + 2)).collect::<Vec<u64>>(), vec![65521, 65537]);
+        assert_eq!(primes_in(0..2).count(), 0);
+        assert_eq!(primes_in(2..3).collect::<Vec<u64>>(), vec![2]);
+        assert_eq!(primes_in(10..10).count(), 0);
+    }
+
+    #[test]
+    fn sieve_works_near_the_top_of_u64() {
+        assert_eq!(primes_in(1_000_000_000_000..1_000_000_000_040).collect::<Vec<u64>>(), vec![1_000_000_000_039]);
+        // Far past SIEVING_PRIME_BOUND squared, so these go through is_prime_u64
+        let top: Vec<u64> = primes_in((u64::MAX - 99)..u64::MAX).collect();
+        assert_eq!(top, vec![u64::MAX - 94, u64::MAX - 82, u64::MAX - 58]);
+    }
+
+    #[test]
+    fn is_prime_u64_rejects_pseudoprimes() {
+        for num in 0..100_000u64 {
+            assert_eq!(is_prime_u64(num), is_prime_by_trial_division(num), "{}", num);
+        }
+        // Carmichael numbers, and strong pseudoprimes to the first few prime bases
+        for carmichael in [561, 1105, 1729, 2465, 2821, 6601, 8911, 3_215_031_751, 3_825_123_056_546_413_051] {
+            assert!(!is_prime_u64(carmichael), "{}", carmichael);
+        }
+        // The two biggest 32-bit primes, and their product
+        assert!(is_prime_u64(u32::MAX as u64 - 4) && is_prime_u64(u32::MAX as u64 - 16));
+        assert!(!is_prime_u64((u32::MAX as u64 - 4) * (u32::MAX as u64 - 16)));
+        assert!(is_prime_u64((1 << 61) - 1));
+        assert!(is_prime_u64(u64::MAX - 58));
+        assert!(!is_prime_u64(u64::MAX));
+    }
+}
@@ -0,0 +1,139 @@
+/* Timing RSA key generation, for the keygen bench command. Each size gets its own NumberHandler,
+    so the prime search stats only cover that size's keys: how many candidates trial division threw
+    out, how many got as far as the bignum tests and failed, and how many Miller-Rabin rounds went
+    on those composites. Generation times vary a lot with how far the search walks before it finds
+    a prime, so the tail matters as much as the mean. */
+
+use std::fmt;
+use std::fmt::Display;
+use std::time::{ Duration, Instant };
+
+use crate::keygen::{ self, Key, NumberHandler, PrimeSearchStats, RSAKeyInfo, bigmod };
+
+/// The sizes keygen bench covers by default. A Key can't hold a 4096-bit modulus, so the last
+/// one is the biggest RSA modulus it can, from two 255-byte primes
+pub const DEFAULT_MODULUS_BITS: [u32; 4] = [1024, 2048, 3072, 4080];
+// How long to spend measuring modexp throughput at each size
+const MODEXP_MEASURE_TIME: Duration = Duration::from_millis(500);
+
+/// Timings and prime search totals for a batch of keys of one size
+pub struct KeyGenProfile {
+    /// The size of the keys' moduli
+    pub modulus_bits: u32,
+    /// How long each key took, fastest first
+    pub times: Vec<Duration>,
+    /// The prime searches behind every key in the batch
+    pub stats: PrimeSearchStats,
+    /// Private key operations per second: modexps by the private exponent mod the modulus
+    pub modexp_per_second: f64
+}
+impl KeyGenProfile {
+    /// The mean time per key, or zero if there are no keys
+    pub fn mean(&self) -> Duration {
+        if self.times.is_empty() { return Duration::ZERO; }
+        self.times.iter().sum::<Duration>() / self.times.len() as u32
+    }
+    /// The time that this percentage of keys came in under, by nearest rank
+    pub fn percentile(&self, percent: u32) -> Duration {
+        if self.times.is_empty() { return Duration::ZERO; }
+        let rank: usize = (self.times.len() * percent as usize).div_ceil(100).max(1);
+        self.times[rank.min(self.times.len()) - 1]
+    }
+    /// The slowest key's time, or zero if there are no keys
+    pub fn max(&self) -> Duration {
+        self.times.last().copied().unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Generate this many RSA keys with a modulus of the given size, and time them. The primes
+/// come in whole bytes, so the size has to be a multiple of 16
+pub fn profile_rsa_keys(modulus_bits: u32, keys: usize) -> Result<KeyGenProfile, keygen::Error> {
+    if !modulus_bits.is_multiple_of(16) { return Err(keygen::Error::InvalidBitSize(modulus_bits)); }
+    let prime_byte_size: usize = (modulus_bits >> 4) as usize;
+    keygen::check_rsa_prime_size(prime_byte_size)?;
+    let mut handler = NumberHandler::new(prime_byte_size)?;
+
+    let mut times: Vec<Duration> = Vec::with_capacity(keys);
+    let mut last_keys: Option<RSAKeyInfo> = None;
+    for _key in 0..keys.max(1) {
+        let start: Instant = Instant::now();
+        let generated: RSAKeyInfo = handler.get_rsa_keys()?;
+        times.push(start.elapsed());
+        last_keys = Some(generated);
+    }
+    times.sort();
+    let stats: PrimeSearchStats = handler.search_stats();
+
+    let modexp_per_second: f64 = match &last_keys {
+        Some(generated) => {
+            let base: Key = handler.get_random_in_range(Key::TWO..generated.shared)?;
+            measure_modexp(base, generated.private, generated.shared)?
+        },
+        None => 0.0
+    };
+    Ok(KeyGenProfile { modulus_bits, times, stats, modexp_per_second })
+}
+
+// Run modexps for MODEXP_MEASURE_TIME, and at least a few times for big moduli that take longer
+fn measure_modexp(base: Key, exponent: Key, modulus: Key) -> Result<f64, keygen::Error> {
+    let start: Instant = Instant::now();
+    let mut operations: u32 = 0;
+    while operations < 3 || start.elapsed() < MODEXP_MEASURE_TIME {
+        std::hint::black_box(bigmod(std::hint::black_box(base), exponent, modulus)?);
+        operations += 1;
+    }
+    Ok(operations as f64 / start.elapsed().as_secs_f64())
+}
+
+impl Display for KeyGenProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats: &PrimeSearchStats = &self.stats;
+        writeln!(f, "{}-bit keys ({} generated)", self.modulus_bits, self.times.len())?;
+        writeln!(f, "  time       mean {:.3?}, p50 {:.3?}, p90 {:.3?}, max {:.3?}",
+            self.mean(), self.percentile(50), self.percentile(90), self.max())?;
+        writeln!(f, "  candidates {} for {} primes: {} rejected by trial division, {} by primality tests",
+            stats.candidates, stats.primes_found, stats.rejected_by_trial_division, stats.rejected_by_primality_test)?;
+        writeln!(f, "  rounds     {} Miller-Rabin rounds, {} of them on composites",
+            stats.miller_rabin_rounds, stats.rounds_on_composites)?;
+        write!(f, "  modexp     {:.1} private key operations per second", self.modexp_per_second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile_with_times(millis: &[u64]) -> KeyGenProfile {
+        let times: Vec<Duration> = millis.iter().map(|&millis| Duration::from_millis(millis)).collect();
+        KeyGenProfile { modulus_bits: 1024, times, stats: PrimeSearchStats::default(), modexp_per_second: 0.0 }
+    }
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let profile: KeyGenProfile = profile_with_times(&[10, 20, 30, 40, 50, 60, 70, 80, 90, 100]);
+        let expected: [(u32, u64); 8] = [(0, 10), (1, 10), (10, 10), (11, 20), (50, 50), (90, 90), (91, 100), (100, 100)];
+        for (percent, millis) in expected {
+            assert_eq!(profile.percentile(percent), Duration::from_millis(millis), "p{}", percent);
+        }
+        assert_eq!(profile.mean(), Duration::from_millis(55));
+        assert_eq!(profile.max(), Duration::from_millis(100));
+
+        // Rounding the rank up, with fewer keys than percentage points
+        let profile: KeyGenProfile = profile_with_times(&[5, 7, 9]);
+        assert_eq!(profile.percentile(33), Duration::from_millis(5));
+        assert_eq!(profile.percentile(34), Duration::from_millis(7));
+        assert_eq!(profile.percentile(50), Duration::from_millis(7));
+        assert_eq!(profile.percentile(67), Duration::from_millis(9));
+
+        let empty: KeyGenProfile = profile_with_times(&[]);
+        assert_eq!(empty.percentile(50), Duration::ZERO);
+        assert_eq!(empty.mean(), Duration::ZERO);
+        assert_eq!(empty.max(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_sizes_that_arent_whole_bytes() {
+        assert_eq!(profile_rsa_keys(1000, 1).err(), Some(keygen::Error::InvalidBitSize(1000)));
+        assert_eq!(profile_rsa_keys(16, 1).err(), Some(keygen::Error::InvalidKeySize(1)));
+    }
+}
@@ -0,0 +1,206 @@
+/* The transport server. It hands out RSA keys from a pool, and can start from keys in a key store
+    or a saved pool snapshot instead of waiting for new ones to generate. */
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use rand::rngs::ThreadRng;
+
+use crate::keygen::{ self, Key, NumberHandler, RSAKeyInfo, bigmod };
+use crate::keygen::Error as KeyGenError;
+use crate::keypool::{ KeyPool, KeyWorker, PoolConfig, PoolConfigError, PoolStats, SnapshotWriter, WorkerConfig };
+use crate::keystore::{ KeyStore, KeyStoreError };
+
+/// Why a server couldn't be started
+#[derive(Debug)]
+pub enum ServerError {
+    /// The key size is one keygen can't make RSA keys of
+    KeyGen(KeyGenError),
+    /// The pool or worker config was rejected
+    PoolConfig(PoolConfigError),
+    /// The key store couldn't be read
+    KeyStore(KeyStoreError)
+}
+impl From<KeyGenError> for ServerError {
+    fn from(error: KeyGenError) -> Self {
+        ServerError::KeyGen(error)
+    }
+}
+impl From<PoolConfigError> for ServerError {
+    fn from(error: PoolConfigError) -> Self {
+        ServerError::PoolConfig(error)
+    }
+}
+impl From<KeyStoreError> for ServerError {
+    fn from(error: KeyStoreError) -> Self {
+        ServerError::KeyStore(error)
+    }
+}
+
+/// The RSA side of the transport server. Keys come out of a pool that a worker thread keeps
+/// topped up, so handling a client doesn't have to wait for one to generate
+pub struct Server {
+    handler: NumberHandler<ThreadRng>,
+    rsa_keys: Arc<KeyPool<RSAKeyInfo>>,
+    _rsa_worker: KeyWorker,
+    // Saves the pool as it changes, and once more when the server is dropped
+    snapshots: Option<SnapshotWriter>,
+    // How long to wait for the worker when there are no keys yet, before generating one ourselves
+    pool_timeout: Duration
+}
+impl Server {
+    /// Prime size in bytes. The modulus is twice that, and has to fit in what bigmod can handle
+    pub fn new(key_byte_size: usize) -> Result<Self, ServerError> {
+        Self::with_config(key_byte_size, PoolConfig::DEFAULT, WorkerConfig::DEFAULT, Duration::from_secs(5))
+    }
+    /// Start with a given pool and worker config. pool_timeout is how long get_rsa_keys waits
+    /// for the worker before generating a key itself
+    pub fn with_config(key_byte_size: usize, pool_config: PoolConfig, worker_config: WorkerConfig, pool_timeout: Duration) -> Result<Self, ServerError> {
+        keygen::check_rsa_prime_size(key_byte_size)?;
+        let handler: NumberHandler<ThreadRng> = NumberHandler::new(key_byte_size)?;
+
+        let rsa_keys: Arc<KeyPool<RSAKeyInfo>> = Arc::new(KeyPool::new(pool_config)?);
+        let rsa_worker: KeyWorker = KeyWorker::spawn(Arc::clone(&rsa_keys), worker_config, move || {
+            let mut handler: Option<NumberHandler<ThreadRng>> = NumberHandler::new(key_byte_size).ok();
+            move || handler.as_mut()?.get_rsa_keys().ok()
+        })?;
+        Ok(Self{ rsa_keys, _rsa_worker: rsa_worker, snapshots: None, pool_timeout, handler })
+    }
+
+    /// Start from the keys in a key store that have the given usage label, instead of
+    /// waiting for keys to generate. New keys will be the same size as the stored ones
+    pub fn from_key_store(store: &KeyStore, label: &str) -> Result<Self, ServerError> {
+        let keys: Vec<RSAKeyInfo> = store.load_with_label(label)?;
+        let first: &RSAKeyInfo = keys.first().ok_or_else(|| KeyStoreError::NoKeysWithLabel(label.to_string()))?;
+
+        let server = Self::new(((first.prime_a.bits() + 7) >> 3) as usize)?;
+        for key in keys {
+            server.rsa_keys.insert_key(key);
+        }
+        Ok(server)
+    }
+
+    /// Handle a request. This doesn't do anything yet
+    pub fn receive(_request: &Vec<u8>, _response: &mut Vec<u8>) -> bool {
+        true
+    }
+
+    /// A key from the pool, or a new one if the pool stays empty for pool_timeout
+    pub fn get_rsa_keys(&mut self) -> Result<RSAKeyInfo, KeyGenError> {
+        if let Some(keys) = self.rsa_keys.get_random_timeout(self.pool_timeout) { return Ok(keys); }
+        // The worker hasn't made any keys yet, so make one here and share it
+        let keys: RSAKeyInfo = self.handler.get_rsa_keys()?;
+        self.rsa_keys.insert_key(keys.clone());
+        Ok(keys)
+    }
+    /// The key pool's counters
+    pub fn pool_stats(&self) -> PoolStats {
+        self.rsa_keys.stats()
+    }
+
+    /// Warm start the key pool from a snapshot, if there is one. The worker keeps topping it up as usual
+    pub fn load_pool_snapshot(&self, path: &Path, passphrase: &str) -> Result<usize, KeyStoreError> {
+        if !path.exists() { return Ok(0); }
+        self.rsa_keys.load_snapshot(path, passphrase)
+    }
+    /// Save the key pool to a snapshot now. Returns how many keys were saved
+    pub fn save_pool_snapshot(&self, path: &Path, passphrase: &str) -> Result<usize, KeyStoreError> {
+        self.rsa_keys.save_snapshot(path, passphrase)
+    }
+    /// Warm start from a snapshot, then keep it up to date: it's saved every interval while the
+    /// pool is changing, and once more when the server is dropped. Returns how many keys were loaded
+    pub fn start_pool_snapshots(&mut self, path: &Path, passphrase: &str, interval: Duration) -> Result<usize, KeyStoreError> {
+        let loaded: usize = self.load_pool_snapshot(path, passphrase)?;
+        // Replacing a writer stops it, after its final save
+        self.snapshots = Some(SnapshotWriter::spawn(Arc::clone(&self.rsa_keys), path.to_path_buf(), passphrase, interval));
+        Ok(loaded)
+    }
+    /// The error from the last snapshot save that failed, if any
+    pub fn take_snapshot_error(&self) -> Option<KeyStoreError> {
+        self.snapshots.as_ref()?.take_error()
+    }
+    /// Save the snapshot one last time and stop keeping it up to date. Dropping the server does the
+    /// same, but this returns the error if a save failed
+    pub fn stop_pool_snapshots(&mut self) -> Option<KeyStoreError> {
+        self.snapshots.take()?.join()
+    }
+    // fn hash_dhke(&self, dhke: DHKEKeyInfo) -> [u64; 4] {}
+    // fn get_dhke_keys(&mut self, iterations: u8) -> DHKEKeyInfo {
+    //     let shared_base: Key = self.handler.get_random_prime(iterations);
+    //     let shared_mod: Key = self.handler.gen_random_coprime(shared_base);
+    // }
+    /// Serve one client with a key from the pool, returning whether the key worked
+    pub fn handle_client(&mut self) -> bool {
+        let keys: RSAKeyInfo = match self.get_rsa_keys() {
+            Ok(keys) => keys,
+            Err(error) => { eprintln!("SERVER: Failed to get keys: {}", error); return false; }
+        };
+        println!("{}", keys);
+
+        let payload = Key::NINE;
+        let round_trip = bigmod(payload, keys.private, keys.shared)
+            .and_then(|encrypted| bigmod(encrypted, keys.public, keys.shared));
+        match round_trip {
+            Ok(decrypted) => println!("{}", decrypted),
+            Err(error) => { eprintln!("SERVER: Failed to use keys: {}", error); return false; }
+        }
+
+        true
+    }
+}
+
+/// Usage label for keys the server uses to identify itself
+pub const SERVER_KEY_LABEL: &str = "server";
+/// Prime size for a server that doesn't start from stored keys, for a 2048-bit modulus
+pub const DEFAULT_KEY_BYTE_SIZE: usize = 128;
+/// How often a running server saves its key pool snapshot
+pub const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let timeout: Duration = Duration::from_secs(1);
+        let no_capacity: PoolConfig = PoolConfig { capacity: 0, ..PoolConfig::DEFAULT };
+        let no_budget: WorkerConfig = WorkerConfig { cpu_budget: f32::NAN, ..WorkerConfig::DEFAULT };
+
+        assert!(matches!(Server::with_config(16, no_capacity, WorkerConfig::DEFAULT, timeout), Err(ServerError::PoolConfig(PoolConfigError::ZeroCapacity))));
+        assert!(matches!(Server::with_config(16, PoolConfig::DEFAULT, no_budget, timeout), Err(ServerError::PoolConfig(PoolConfigError::InvalidCpuBudget(_)))));
+        assert!(matches!(Server::new(0), Err(ServerError::KeyGen(KeyGenError::InvalidKeySize(0)))));
+        assert!(matches!(Server::new(1000), Err(ServerError::KeyGen(KeyGenError::InvalidKeySize(1000)))));
+    }
+
+    #[test]
+    fn keeps_the_pool_snapshot_up_to_date() {
+        let path = std::env::temp_dir().join(format!("server-snapshot-test-{}.store", std::process::id()));
+        let mut server: Server = Server::new(16).unwrap();
+        assert_eq!(server.start_pool_snapshots(&path, "passphrase", Duration::from_millis(10)).unwrap(), 0);
+        server.get_rsa_keys().unwrap();
+
+        // Saved while running, without waiting for the server to stop
+        let deadline: std::time::Instant = std::time::Instant::now() + Duration::from_secs(60);
+        while !path.exists() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(path.exists());
+        assert!(server.stop_pool_snapshots().is_none());
+        let saved: usize = KeyStore::list(&path).unwrap().len();
+        assert!(saved >= 1);
+        drop(server);
+
+        let mut restarted: Server = Server::with_config(16, PoolConfig::DEFAULT, WorkerConfig::DEFAULT, Duration::ZERO).unwrap();
+        assert_eq!(restarted.start_pool_snapshots(&path, "passphrase", DEFAULT_SNAPSHOT_INTERVAL).unwrap(), saved);
+        drop(restarted);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hands_out_working_keys() {
+        let mut server: Server = Server::new(16).unwrap();
+        let keys: RSAKeyInfo = server.get_rsa_keys().unwrap();
+        assert!(keys.validate().is_ok());
+        assert!(server.handle_client());
+    }
+}
@@ -1,5 +1,7 @@
+#[cfg(target_os = "windows")]
 pub use windows::Win32::Networking::WinSock as WinSock;
 
+/// Start up WinSock. Panics if it won't start
 #[cfg(target_os = "windows")]
 pub fn initialize_sockets() {
     use windows::Win32::Networking::WinSock::{ WSADATA, WSAStartup };
@@ -15,9 +17,11 @@ pub fn initialize_sockets() {
         panic!("ERR (initialize_sockets): WSAStartup failed with error code -> {}", start_code);
     }
 }
-#[cfg(target_os = "linux")]
+/// Nothing needs starting up outside Windows
+#[cfg(not(target_os = "windows"))]
 pub fn initialize_sockets() {}
 
+/// A raw ICMP socket bound to every local address, for the server to listen on
 #[cfg(target_os = "windows")]
 pub fn create_server_socket() -> WinSock::SOCKET {
     use windows::Win32::Networking::WinSock::{
@@ -68,6 +72,7 @@ pub fn create_server_socket() -> WinSock::SOCKET {
 
     socket
 }
+/// Receive on the socket forever, logging how much arrives
 #[cfg(target_os = "windows")]
 pub fn server_listen(sock: WinSock::SOCKET) {
     use windows::Win32::Networking::WinSock::{
@@ -111,6 +116,8 @@ pub fn server_listen(sock: WinSock::SOCKET) {
     }
 }
 
+/// A raw ICMP socket for a client to send from
+#[cfg(target_os = "windows")]
 pub fn create_client_socket() -> WinSock::SOCKET {
     use windows::Win32::Networking::WinSock::{
         socket, AF_INET, SOCK_RAW, IPPROTO_RAW, IPPROTO_ICMP,
@@ -157,6 +164,7 @@ pub fn create_client_socket() -> WinSock::SOCKET {
     socket
 }
 
+/// Send a test packet from the client socket to the server on localhost
 #[cfg(target_os="windows")]
 pub fn queue_server(client: WinSock::SOCKET, server: WinSock::SOCKET) {
     println!("CLIENT: sending data from socket {:?}", client);
@@ -195,21 +203,27 @@ pub fn queue_server(client: WinSock::SOCKET, server: WinSock::SOCKET) {
         println!("Failed to send IP_RAW packet, with error: {}", std::io::Error::last_os_error());
     }
 }
+/// Close a socket made by one of the functions here
 #[cfg(target_os = "windows")]
 pub fn close_socket(socket: windows::Win32::Networking::WinSock::SOCKET) {
     unsafe { windows::Win32::Networking::WinSock::closesocket(socket) };
 }
+/// Shut WinSock down once every socket is closed
 #[cfg(target_os = "windows")]
 pub fn clean_up() {
     unsafe { windows::Win32::Networking::WinSock::WSACleanup() };
 }
 
+/// The network interfaces a raw socket can be bound to
 #[cfg(target_os = "linux")]
 pub enum NetworkInterface {
+    /// The first Ethernet interface
     ETH0
 }
+/// What a raw socket should be bound to
 #[cfg(target_os = "linux")]
 pub struct RawSocket {
+    /// The interface to receive every Ethernet packet from
     pub interface: NetworkInterface,
 }
 #[cfg(target_os = "linux")]
@@ -218,6 +232,8 @@ fn network_interface_to_string<'a>(interface: NetworkInterface) -> &'a str {
         NetworkInterface::ETH0 => "eth0"
     }
 }
+/// Open an AF_PACKET socket bound to the interface, which gets every Ethernet packet on it.
+/// Panics if the socket can't be made or bound, eg without CAP_NET_RAW
 #[cfg(target_os = "linux")]
 pub fn create_raw_socket(socket_info: RawSocket) {
     use std::ffi::CString;
@@ -229,7 +245,11 @@ pub fn create_raw_socket(socket_info: RawSocket) {
 
     // On Linux, create a raw socket with AF_PACKET (it will go all the way down to the packet network layer)
     // And with ETH_P_ALL, enabling it to get all ethernet packets, regardless of the individial protocol
-    let socket = unsafe { socket(AF_PACKET, SOCK_RAW, (IP_PROTO as u16).to_be() as i32) };
+    let socket = unsafe { socket(AF_PACKET, SOCK_RAW, (ETH_P_ALL as u16).to_be() as i32) };
+
+    if socket < 0 {
+        panic!("ERR (create_raw_socket): Failed to create raw socket: {}", std::io::Error::last_os_error());
+    }
 
     let interface_bind = unsafe {
         setsockopt(
@@ -240,4 +260,7 @@ pub fn create_raw_socket(socket_info: RawSocket) {
             libc::IFNAMSIZ as libc::socklen_t
         )
     };
+    if interface_bind != 0 {
+        panic!("ERR (create_raw_socket): Failed to bind socket to {}: {}", interface_name.to_string_lossy(), std::io::Error::last_os_error());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+/* Wiping secrets out of memory once we're done with them. Plain assignments to memory that's about
+    to be freed are dead stores, which the compiler is free to remove, so the writes here are volatile,
+    followed by a fence so they can't be reordered past whatever frees the memory. */
+
+use core::ops::{ Deref, DerefMut };
+use core::ptr;
+use core::sync::atomic::{ compiler_fence, Ordering };
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::keygen::Key;
+
+/// Something that can be overwritten with zeroes in a way the compiler won't remove
+pub trait Zeroize {
+    /// Overwrite every byte with zero
+    fn zeroize(&mut self);
+}
+
+impl Zeroize for Key {
+    fn zeroize(&mut self) {
+        // SAFETY: self is a valid, aligned Key, and zero is a valid Key
+        unsafe { ptr::write_volatile(self as *mut Key, Key::ZERO); }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+impl Zeroize for [u8] {
+    fn zeroize(&mut self) {
+        for byte in self.iter_mut() {
+            // SAFETY: byte is a valid, aligned u8
+            unsafe { ptr::write_volatile(byte as *mut u8, 0); }
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+impl<const N: usize> Zeroize for [u8; N] {
+    fn zeroize(&mut self) {
+        self.as_mut_slice().zeroize();
+    }
+}
+#[cfg(feature = "alloc")]
+impl Zeroize for Vec<u8> {
+    // Wipes the spare capacity too, since that can hold secrets from before a truncate
+    fn zeroize(&mut self) {
+        self.as_mut_slice().zeroize();
+        let spare = self.spare_capacity_mut();
+        for byte in spare.iter_mut() {
+            // SAFETY: writing a u8 into MaybeUninit<u8> is always valid
+            unsafe { ptr::write_volatile(byte.as_mut_ptr(), 0); }
+        }
+        compiler_fence(Ordering::SeqCst);
+        self.clear();
+    }
+}
+
+/// Owns a secret and wipes it when it goes out of scope, for intermediate values like
+/// the primes and lambda(n) during key generation
+pub struct Zeroizing<T: Zeroize>(T);
+impl<T: Zeroize> Zeroizing<T> {
+    /// Take ownership of the secret
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+impl<T: Zeroize> Deref for Zeroizing<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T: Zeroize> DerefMut for Zeroizing<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+impl<T: Zeroize> Drop for Zeroizing<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn wipes_in_place() {
+        let mut key: Key = Key::MAX;
+        key.zeroize();
+        assert_eq!(key, Key::ZERO);
+
+        let mut bytes: [u8; 33] = [0xa5; 33];
+        bytes.zeroize();
+        assert_eq!(bytes, [0; 33]);
+        bytes.fill(0xa5);
+        bytes[1..5].zeroize();
+        assert_eq!(bytes[..6], [0xa5, 0, 0, 0, 0, 0xa5]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn wipes_spare_capacity() {
+        let mut secret: Vec<u8> = alloc::vec![0xa5; 64];
+        secret.truncate(16);
+        secret.zeroize();
+        assert!(secret.is_empty());
+        // SAFETY: zeroize wrote every byte of the spare capacity, so it's all initialized
+        let spare: &[MaybeUninit<u8>] = secret.spare_capacity_mut();
+        assert!(spare.len() >= 64);
+        assert!(spare.iter().all(|byte| unsafe { byte.assume_init() } == 0));
+    }
+
+    #[test]
+    fn wipes_on_drop() {
+        // Drop the value where it is, then look at what's left behind
+        let mut slot: MaybeUninit<Zeroizing<[u8; 32]>> = MaybeUninit::new(Zeroizing::new([0xa5; 32]));
+        // SAFETY: the slot was initialized just above, and a byte array is still valid to read after its drop
+        let left: [u8; 32] = unsafe {
+            slot.assume_init_drop();
+            ptr::read(ptr::addr_of!((*slot.as_ptr()).0))
+        };
+        assert_eq!(left, [0; 32]);
+
+        let mut slot: MaybeUninit<Zeroizing<Key>> = MaybeUninit::new(Zeroizing::new(Key::MAX));
+        // SAFETY: the same, for a Key
+        let left: Key = unsafe {
+            slot.assume_init_drop();
+            ptr::read(ptr::addr_of!((*slot.as_ptr()).0))
+        };
+        assert_eq!(left, Key::ZERO);
+    }
+}